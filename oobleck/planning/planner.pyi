@@ -1,9 +1,268 @@
+import os
+
 from cornstarch.pipeline_template import PipelineTemplate
 
-from oobleck.planning.profiler import LayerExecutionResult
+from oobleck.planning.profiler import DeviceClass, LayerExecutionResult
+
+# Every error the planner raises is a PlannerError (a RuntimeError) subclass
+# whose attributes describe what went wrong.
+class PlannerError(RuntimeError):
+    reason: str
+
+class InfeasibleNodeCountError(PlannerError):
+    num_nodes: list[int]
+
+class MemoryExceededError(PlannerError):
+    num_nodes: list[int]
+    layers: tuple[int, int]
+    required: int
+    budget: int
+
+class InvalidProfileError(PlannerError):
+    layer_index: int | None
+
+class InvalidConfigError(PlannerError): ...
+
+# Raised when planning is cancelled with its cancellation_token, or runs longer
+# than its timeout in seconds.
+class CancelledError(PlannerError): ...
+
+# Planning runs without holding the GIL, so another thread can cancel it,
+# e.g. when a reconfiguration makes the pipelines being planned obsolete.
+class CancellationToken:
+    def __init__(self) -> None: ...
+    def cancel(self) -> None: ...
+    @property
+    def cancelled(self) -> bool: ...
+
+# Planner options are keyword-only and shared by all entry points.
+# With pareto_frontier, create_pipeline_templates returns for each number of nodes
+# the templates not beaten in both latency and peak stage memory, from the fastest.
+# With top_k, it returns the k fastest distinct templates for each number of nodes.
+# Entry points returning a single template per key raise InvalidConfigError for both.
+# Templates have a `stage_memory` attribute with the peak memory of each stage.
+# Templates are keyed by number of nodes. With gpus_per_node > 1, num_nodes counts
+# nodes that run one stage per GPU, and templates have a `stages_per_node` attribute
+# listing the stages on each node.
+# Heterogeneous templates additionally have a `device_classes` attribute
+# with the name of the device class each stage runs on.
 
 def create_pipeline_templates(
     model_name: str,
     profile_data: list[LayerExecutionResult],
     num_nodes: list[int],
-) -> dict[int, PipelineTemplate]: ...
+    *,
+    link_bandwidth: float | None = None,
+    link_latency: float = 0.0,
+    gpus_per_node: int = 1,
+    intra_node_bandwidth: float | None = None,
+    intra_node_latency: float = 0.0,
+    num_microbatches: int | None = None,
+    max_num_microbatches: int | None = None,
+    schedule: str = "1f1b",
+    num_model_chunks: int = 1,
+    latency_statistic: str = "mean",
+    device_memory: int | None = None,
+    memory_headroom: float = 0.0,
+    precision: str | None = None,
+    optimizer: str | None = None,
+    pareto_frontier: bool = False,
+    top_k: int | None = None,
+    cancellation_token: CancellationToken | None = None,
+    timeout: float | None = None,
+) -> dict[int, PipelineTemplate] | dict[int, list[PipelineTemplate]]: ...
+
+# Plans from a profile file written by the profiler. Raises InvalidProfileError
+# if the profile was taken for another model, or for another tp_size,
+# microbatch_size or precision when those are given. With an optimizer, memory
+# is modeled in the precision of the profile.
+def create_pipeline_templates_from_profile(
+    model_name: str,
+    profile_path: str | os.PathLike,
+    num_nodes: list[int],
+    *,
+    tp_size: int | None = None,
+    microbatch_size: int | None = None,
+    precision: str | None = None,
+    link_bandwidth: float | None = None,
+    link_latency: float = 0.0,
+    gpus_per_node: int = 1,
+    intra_node_bandwidth: float | None = None,
+    intra_node_latency: float = 0.0,
+    num_microbatches: int | None = None,
+    max_num_microbatches: int | None = None,
+    schedule: str = "1f1b",
+    num_model_chunks: int = 1,
+    latency_statistic: str = "mean",
+    device_memory: int | None = None,
+    memory_headroom: float = 0.0,
+    optimizer: str | None = None,
+    pareto_frontier: bool = False,
+    top_k: int | None = None,
+    cancellation_token: CancellationToken | None = None,
+    timeout: float | None = None,
+) -> dict[int, PipelineTemplate] | dict[int, list[PipelineTemplate]]: ...
+def save_profile(
+    profile_path: str | os.PathLike,
+    model_name: str,
+    microbatch_size: int,
+    tp_size: int,
+    precision: str,
+    profile_data: list[LayerExecutionResult],
+) -> None: ...
+
+# Merges profile files of several runs or nodes. Runs consistently slower or faster
+# than the others (e.g. a throttled node) are left out, and latencies are combined
+# with the estimator ("mean", "median" or "trimmed_mean"). The consolidated profile
+# keeps the spread over runs and is written to output_path if given. Returns
+# {"outlier_runs": [...], "layers": [{"layer_name", "mean", "median",
+# "trimmed_mean", "std", "confidence_interval", ...}, ...]}.
+def aggregate_profiles(
+    profile_paths: list[str | os.PathLike],
+    output_path: str | os.PathLike | None = None,
+    *,
+    estimator: str = "trimmed_mean",
+    trim_fraction: float = 0.1,
+) -> dict: ...
+
+# Predicts the profile of microbatch_size from profiles of at least two other sizes,
+# with latency and memory affine in the microbatch size, and writes it to
+# output_path. Returns {"latency_error": ..., "memory_error": ...}, the largest
+# relative residuals of the fit (zero with exactly two sizes).
+def interpolate_profile(
+    profile_paths: list[str | os.PathLike],
+    microbatch_size: int,
+    output_path: str | os.PathLike,
+) -> dict[str, float]: ...
+
+# Predicts the profile of a transformer from its HuggingFace config.json (or a
+# directory containing it) and a device roofline: peak_flops in FLOP/s and
+# memory_bandwidth in bytes/s. Writes the profile to output_path, and returns the
+# model name to plan it under with create_pipeline_templates_from_profile.
+def synthesize_profile_from_config(
+    config_path: str | os.PathLike,
+    output_path: str | os.PathLike,
+    *,
+    peak_flops: float,
+    memory_bandwidth: float,
+    microbatch_size: int = 1,
+    seq_length: int | None = None,
+    tp_size: int = 1,
+    precision: str = "bf16",
+    optimizer: str = "adamw",
+) -> str: ...
+
+# Layer timings from a torch.profiler Chrome trace, in which every forward pass
+# of a layer is a CPU scope named after the layer (e.g. with record_function).
+# Memory is not traced; set it before planning with a memory budget.
+def import_chrome_trace(
+    trace_path: str | os.PathLike, layer_names: list[str]
+) -> list[LayerExecutionResult]: ...
+def create_heterogeneous_pipeline_template(
+    model_name: str,
+    profile_data: list[LayerExecutionResult],
+    device_classes: list[DeviceClass],
+    num_nodes: list[int],
+    *,
+    link_bandwidth: float | None = None,
+    link_latency: float = 0.0,
+    gpus_per_node: int = 1,
+    intra_node_bandwidth: float | None = None,
+    intra_node_latency: float = 0.0,
+    num_microbatches: int | None = None,
+    max_num_microbatches: int | None = None,
+    schedule: str = "1f1b",
+    num_model_chunks: int = 1,
+    latency_statistic: str = "mean",
+    device_memory: int | None = None,
+    memory_headroom: float = 0.0,
+    precision: str | None = None,
+    optimizer: str | None = None,
+    cancellation_token: CancellationToken | None = None,
+    timeout: float | None = None,
+) -> PipelineTemplate: ...
+
+# Profiles are keyed by tensor parallel degree. Returns the chosen degree
+# and template for each number of GPUs. Every degree must divide gpus_per_node
+# or be a multiple of it.
+def create_tensor_parallel_pipeline_templates(
+    model_name: str,
+    profile_data: dict[int, list[LayerExecutionResult]],
+    num_gpus: list[int],
+    *,
+    link_bandwidth: float | None = None,
+    link_latency: float = 0.0,
+    gpus_per_node: int = 1,
+    intra_node_bandwidth: float | None = None,
+    intra_node_latency: float = 0.0,
+    num_microbatches: int | None = None,
+    max_num_microbatches: int | None = None,
+    schedule: str = "1f1b",
+    num_model_chunks: int = 1,
+    latency_statistic: str = "mean",
+    device_memory: int | None = None,
+    memory_headroom: float = 0.0,
+    precision: str | None = None,
+    optimizer: str | None = None,
+    cancellation_token: CancellationToken | None = None,
+    timeout: float | None = None,
+) -> dict[int, tuple[int, PipelineTemplate]]: ...
+
+# Profiles are keyed by microbatch size. Every pipeline runs global_batch_size
+# samples per iteration, so the number of microbatches is not an option.
+# Returns the chosen microbatch size and template for each number of nodes.
+def create_microbatch_pipeline_templates(
+    model_name: str,
+    profile_data: dict[int, list[LayerExecutionResult]],
+    num_nodes: list[int],
+    global_batch_size: int,
+    *,
+    link_bandwidth: float | None = None,
+    link_latency: float = 0.0,
+    gpus_per_node: int = 1,
+    intra_node_bandwidth: float | None = None,
+    intra_node_latency: float = 0.0,
+    schedule: str = "1f1b",
+    num_model_chunks: int = 1,
+    latency_statistic: str = "mean",
+    device_memory: int | None = None,
+    memory_headroom: float = 0.0,
+    precision: str | None = None,
+    optimizer: str | None = None,
+    cancellation_token: CancellationToken | None = None,
+    timeout: float | None = None,
+) -> dict[int, tuple[int, PipelineTemplate]]: ...
+
+# Keeps the pipelines it planned between calls, so that planning for more nodes,
+# e.g. once the cluster has grown, only plans the pipelines with more stages.
+class PipelineTemplatePlanner:
+    def __init__(
+        self,
+        model_name: str,
+        profile_data: list[LayerExecutionResult],
+        *,
+        link_bandwidth: float | None = None,
+        link_latency: float = 0.0,
+        gpus_per_node: int = 1,
+        intra_node_bandwidth: float | None = None,
+        intra_node_latency: float = 0.0,
+        num_microbatches: int | None = None,
+        max_num_microbatches: int | None = None,
+        schedule: str = "1f1b",
+        num_model_chunks: int = 1,
+        latency_statistic: str = "mean",
+        device_memory: int | None = None,
+        memory_headroom: float = 0.0,
+        precision: str | None = None,
+        optimizer: str | None = None,
+        pareto_frontier: bool = False,
+        top_k: int | None = None,
+    ) -> None: ...
+    # Pipelines planned before a cancellation are kept for later calls.
+    def create_pipeline_templates(
+        self,
+        num_nodes: list[int],
+        *,
+        cancellation_token: CancellationToken | None = None,
+        timeout: float | None = None,
+    ) -> dict[int, PipelineTemplate] | dict[int, list[PipelineTemplate]]: ...
@@ -29,6 +29,33 @@ class LayerExecutionResult:
     forward: float
     backward: float
     mem_required: int
+    activation_size: int = 0
+    param_mem: int = 0
+    activation_mem: int = 0
+    num_parameters: int = 0
+    recompute: float = 0.0
+    checkpoint_mem_saved: int = 0
+    forward_std: float = 0.0
+    backward_std: float = 0.0
+    samples: list[float] = field(default_factory=list)
+
+
+@dataclass
+class DeviceClass:
+    """A kind of accelerator in a heterogeneous cluster.
+
+    Args:
+        name (str): Name of the device class, e.g. "a100".
+        speed (float): Throughput relative to the device `profile_data` was taken on.
+        memory (int | None): Device memory in bytes. Falls back to `device_memory`.
+        profile (list[LayerExecutionResult] | None): Profile taken on this device class,
+            used instead of scaling the shared profile by `speed`.
+    """
+
+    name: str
+    speed: float = 1.0
+    memory: int | None = None
+    profile: list[LayerExecutionResult] | None = None
 
 
 class JsonEncoder(json.JSONEncoder):
@@ -149,6 +176,15 @@ class ModelProfiler:
                 forward=layer["forward"],
                 backward=layer["backward"],
                 mem_required=layer["mem_required"],
+                activation_size=layer.get("activation_size", 0),
+                param_mem=layer.get("param_mem", 0),
+                activation_mem=layer.get("activation_mem", 0),
+                num_parameters=layer.get("num_parameters", 0),
+                recompute=layer.get("recompute", 0.0),
+                checkpoint_mem_saved=layer.get("checkpoint_mem_saved", 0),
+                forward_std=layer.get("forward_std", 0.0),
+                backward_std=layer.get("backward_std", 0.0),
+                samples=layer.get("samples", []),
             )
             for layer in data["layers"]
         ]
@@ -184,6 +220,10 @@ class ModelProfiler:
             module_name: str
             events: dict[EventTiming, torch.cuda.Event] = field(default_factory=dict)
             memory: dict[EventTiming, int] = field(default_factory=dict)
+            activation_size: int = 0
+            num_parameters: int = 0
+            # Bytes of weights, gradients and master weights, excluding optimizer states
+            param_mem: int = 0
 
         store_path = profile_dir / "store"
         logger.debug(
@@ -264,6 +304,11 @@ class ModelProfiler:
             )
             event = profile_data[module_name].events[EventTiming.FORWARD_END]
             event.record()
+            profile_data[module_name].activation_size = sum(
+                t.numel() * t.element_size()
+                for t in torch.utils._pytree.tree_leaves(outputs)
+                if isinstance(t, torch.Tensor)
+            )
             module.to("cpu")
 
         modules_to_offload: list[tuple[str, torch.nn.Module]] = []
@@ -356,11 +401,19 @@ class ModelProfiler:
                         if f"{layer_name}.{param_name}" in model._tied_weights_keys:
                             continue
 
+                        profile_data[layer_name].num_parameters += p.numel()
+                        profile_data[layer_name].param_mem += (
+                            2 * p.numel() * p.element_size()
+                        )
+
                         if precision in ["fp16", "bf16"]:
                             optim_param_index_id = optim_param_info["id2param"][
                                 num_parameters
                             ]
                             master_tensor = working_to_master_map[optim_param_index_id]
+                            profile_data[layer_name].param_mem += (
+                                master_tensor.numel() * master_tensor.element_size()
+                            )
                             states: dict[torch.Tensor, dict] = (
                                 optimizer.optim.state.get(master_tensor)
                             )
@@ -398,6 +451,19 @@ class ModelProfiler:
                 "layers": [],
             }
             for index, (layer_name, layer_profile) in enumerate(profile_data.items()):
+                # Memory deltas can be negative when the allocator frees tensors
+                # in between, and the planner takes memory as unsigned bytes
+                activation_mem = max(
+                    layer_profile.memory[EventTiming.FORWARD_END]
+                    - layer_profile.memory[EventTiming.FORWARD_START],
+                    0,
+                )
+                # Static memory is measured from the tensors themselves rather than
+                # from allocator deltas around backward, which frees activations
+                param_mem = layer_profile.param_mem + (
+                    layer_profile.memory[EventTiming.OPTIMIZER_STEP_END]
+                    - layer_profile.memory[EventTiming.OPTIMIZER_STEP_START]
+                )
                 data["layers"].append(
                     asdict(
                         LayerExecutionResult(
@@ -413,18 +479,11 @@ class ModelProfiler:
                             ].elapsed_time(
                                 layer_profile.events[EventTiming.BACKWARD_END]
                             ),
-                            mem_required=(
-                                layer_profile.memory[EventTiming.FORWARD_END]
-                                - layer_profile.memory[EventTiming.FORWARD_START]
-                            )
-                            + (
-                                layer_profile.memory[EventTiming.BACKWARD_END]
-                                - layer_profile.memory[EventTiming.BACKWARD_START]
-                            )
-                            + (
-                                layer_profile.memory[EventTiming.OPTIMIZER_STEP_END]
-                                - layer_profile.memory[EventTiming.OPTIMIZER_STEP_START]
-                            ),
+                            mem_required=activation_mem + param_mem,
+                            param_mem=param_mem,
+                            activation_mem=activation_mem,
+                            activation_size=layer_profile.activation_size,
+                            num_parameters=layer_profile.num_parameters,
                         )
                     )
                 )
@@ -0,0 +1,351 @@
+use crate::execution_result::{LayerExecutionResult, ProfileResult};
+use crate::validation::ProfileError;
+use crate::PlannerError;
+
+/// How the latencies of several runs are combined into one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Estimator {
+    Mean,
+    Median,
+    // Mean without the given fraction of the smallest and of the largest values
+    TrimmedMean(f64),
+}
+
+impl Estimator {
+    pub fn from_name(name: &str, trim_fraction: f64) -> Result<Self, PlannerError> {
+        match name.to_lowercase().as_str() {
+            "mean" => Ok(Estimator::Mean),
+            "median" => Ok(Estimator::Median),
+            "trimmed_mean" if (0.0..0.5).contains(&trim_fraction) => {
+                Ok(Estimator::TrimmedMean(trim_fraction))
+            }
+            "trimmed_mean" => Err(PlannerError::invalid_config(format!(
+                "Trim fraction must be in [0, 0.5), got {}",
+                trim_fraction
+            ))),
+            _ => Err(PlannerError::invalid_config(format!(
+                "Unknown estimator: {}",
+                name
+            ))),
+        }
+    }
+
+    fn estimate(&self, values: &[f64]) -> f64 {
+        match self {
+            Estimator::Mean => mean(values),
+            Estimator::Median => median(values),
+            Estimator::TrimmedMean(fraction) => trimmed_mean(values, *fraction),
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn sorted(values: &[f64]) -> Vec<f64> {
+    let mut values = values.to_vec();
+    values.sort_by(f64::total_cmp);
+    values
+}
+
+fn median(values: &[f64]) -> f64 {
+    let values = sorted(values);
+    let n = values.len();
+    if n % 2 == 1 {
+        values[n / 2]
+    } else {
+        (values[n / 2 - 1] + values[n / 2]) / 2.0
+    }
+}
+
+fn trimmed_mean(values: &[f64], fraction: f64) -> f64 {
+    let values = sorted(values);
+    let num_trimmed = (values.len() as f64 * fraction).floor() as usize;
+    mean(&values[num_trimmed..values.len() - num_trimmed])
+}
+
+fn std(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(values);
+    (values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / (values.len() - 1) as f64)
+        .sqrt()
+}
+
+/// Statistics of the forward + backward latency (ms) of a layer over the runs kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerStatistics {
+    pub layer_index: u32,
+    pub layer_name: String,
+    pub mean: f64,
+    pub median: f64,
+    pub trimmed_mean: f64,
+    pub std: f64,
+    // Half width of the 95% confidence interval of the mean
+    pub confidence_interval: f64,
+}
+
+pub struct AggregatedProfile {
+    // Consolidated profile, with the spread over runs as standard deviations and samples
+    pub profile: ProfileResult,
+    pub layers: Vec<LayerStatistics>,
+    // Indices of the runs left out as outliers
+    pub outlier_runs: Vec<usize>,
+}
+
+// Modified z-score above which a run is an outlier (Iglewicz and Hoaglin)
+const OUTLIER_Z_SCORE: f64 = 3.5;
+// Relative slowdown that makes a run an outlier if all other runs agree exactly
+const OUTLIER_TOLERANCE: f64 = 0.05;
+
+/// Runs that are consistently slower or faster than the others, e.g. a throttled node.
+///
+/// Each run is scored by the median over layers of its latency relative to the median
+/// latency of the layer, and runs whose score deviates from the others are outliers.
+fn find_outlier_runs(latencies: &[Vec<f64>]) -> Vec<usize> {
+    let num_runs = latencies.len();
+    if num_runs < 3 {
+        return vec![];
+    }
+
+    let num_layers = latencies[0].len();
+    let layer_medians: Vec<f64> = (0..num_layers)
+        .map(|l| median(&latencies.iter().map(|run| run[l]).collect::<Vec<f64>>()))
+        .collect();
+    let scores: Vec<f64> = latencies
+        .iter()
+        .map(|run| {
+            let ratios: Vec<f64> = run
+                .iter()
+                .zip(layer_medians.iter())
+                .filter(|(_, &median)| median > 0.0)
+                .map(|(latency, median)| latency / median)
+                .collect();
+            if ratios.is_empty() {
+                1.0
+            } else {
+                median(&ratios)
+            }
+        })
+        .collect();
+
+    let center = median(&scores);
+    let deviation = median(
+        &scores
+            .iter()
+            .map(|score| (score - center).abs())
+            .collect::<Vec<f64>>(),
+    );
+    (0..num_runs)
+        .filter(|&r| {
+            if deviation > 0.0 {
+                0.6745 * (scores[r] - center).abs() / deviation > OUTLIER_Z_SCORE
+            } else {
+                (scores[r] - center).abs() > OUTLIER_TOLERANCE * center
+            }
+        })
+        .collect()
+}
+
+/// Checks that all profiles were taken for the same model and configuration.
+fn check_same_configuration(profiles: &[ProfileResult]) -> Result<(), ProfileError> {
+    let first = &profiles[0];
+    for profile in profiles.iter() {
+        profile.validate()?;
+        first.check_same_layers(profile)?;
+        profile.check_metadata(
+            first.model_name.as_str(),
+            None,
+            Some(first.microbatch_size),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Merges profiles of the same model from several runs or nodes into one.
+///
+/// Outlier runs are left out, then latencies are combined with the estimator and
+/// memory takes the largest value of any run.
+pub fn aggregate_profiles(
+    profiles: &[ProfileResult],
+    estimator: Estimator,
+) -> Result<AggregatedProfile, ProfileError> {
+    if profiles.is_empty() {
+        return Err(ProfileError::NoProfiles);
+    }
+    check_same_configuration(profiles)?;
+
+    let latencies: Vec<Vec<f64>> = profiles
+        .iter()
+        .map(|profile| {
+            profile
+                .layers
+                .iter()
+                .map(|layer| layer.forward + layer.backward)
+                .collect()
+        })
+        .collect();
+    let outlier_runs = find_outlier_runs(&latencies);
+    let kept: Vec<&ProfileResult> = profiles
+        .iter()
+        .enumerate()
+        .filter(|(r, _)| !outlier_runs.contains(r))
+        .map(|(_, profile)| profile)
+        .collect();
+
+    let first = &profiles[0];
+    let mut layers: Vec<LayerExecutionResult> = Vec::with_capacity(first.layers.len());
+    let mut statistics: Vec<LayerStatistics> = Vec::with_capacity(first.layers.len());
+    for (l, layer) in first.layers.iter().enumerate() {
+        let runs: Vec<&LayerExecutionResult> =
+            kept.iter().map(|profile| &profile.layers[l]).collect();
+        let values = |field: fn(&LayerExecutionResult) -> f64| -> Vec<f64> {
+            runs.iter().map(|&layer| field(layer)).collect()
+        };
+        let max = |field: fn(&LayerExecutionResult) -> u64| -> u64 {
+            runs.iter().map(|&layer| field(layer)).max().unwrap_or(0)
+        };
+
+        let forward = values(|layer| layer.forward);
+        let backward = values(|layer| layer.backward);
+        let total = values(|layer| layer.forward + layer.backward);
+
+        layers.push(LayerExecutionResult {
+            layer_index: layer.layer_index,
+            layer_name: layer.layer_name.clone(),
+            forward: estimator.estimate(&forward),
+            backward: estimator.estimate(&backward),
+            mem_required: max(|layer| layer.mem_required),
+            activation_size: max(|layer| layer.activation_size),
+            param_mem: max(|layer| layer.param_mem),
+            activation_mem: max(|layer| layer.activation_mem),
+            num_parameters: layer.num_parameters,
+            recompute: estimator.estimate(&values(|layer| layer.recompute)),
+            checkpoint_mem_saved: max(|layer| layer.checkpoint_mem_saved),
+            forward_std: std(&forward),
+            backward_std: std(&backward),
+            samples: total.clone(),
+        });
+        statistics.push(LayerStatistics {
+            layer_index: layer.layer_index,
+            layer_name: layer.layer_name.clone(),
+            mean: mean(&total),
+            median: median(&total),
+            trimmed_mean: trimmed_mean(
+                &total,
+                match estimator {
+                    Estimator::TrimmedMean(fraction) => fraction,
+                    _ => 0.1,
+                },
+            ),
+            std: std(&total),
+            confidence_interval: 1.96 * std(&total) / (total.len() as f64).sqrt(),
+        });
+    }
+
+    Ok(AggregatedProfile {
+        profile: ProfileResult::new(
+            first.model_name.clone(),
+            first.microbatch_size,
+            first.tp_size,
+            first.precision.clone(),
+            layers,
+        ),
+        layers: statistics,
+        outlier_runs,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profile(latencies: &[f64], mem_required: u64) -> ProfileResult {
+        let layers = latencies
+            .iter()
+            .enumerate()
+            .map(|(i, &latency)| LayerExecutionResult {
+                layer_index: i as u32,
+                layer_name: format!("layer{}", i),
+                forward: latency / 2.0,
+                backward: latency / 2.0,
+                mem_required,
+                ..Default::default()
+            })
+            .collect();
+        ProfileResult::new("gpt2".to_string(), 1, 1, "fp32".to_string(), layers)
+    }
+
+    #[test]
+    fn test_estimators() {
+        let values = [1.0, 2.0, 3.0, 4.0, 100.0];
+        assert_eq!(Estimator::Mean.estimate(&values), 22.0);
+        assert_eq!(Estimator::Median.estimate(&values), 3.0);
+        assert_eq!(Estimator::TrimmedMean(0.2).estimate(&values), 3.0);
+        assert_eq!(Estimator::Median.estimate(&[1.0, 2.0]), 1.5);
+        assert!(Estimator::from_name("trimmed_mean", 0.5).is_err());
+        assert!(Estimator::from_name("mode", 0.1).is_err());
+    }
+
+    #[test]
+    fn test_leave_out_throttled_run() {
+        let profiles = vec![
+            profile(&[2.0, 4.0, 6.0], 10),
+            profile(&[2.2, 4.0, 5.8], 12),
+            // Throttled node
+            profile(&[3.0, 6.0, 9.0], 10),
+            profile(&[1.8, 4.0, 6.2], 10),
+        ];
+        let aggregated = aggregate_profiles(&profiles, Estimator::Mean).unwrap();
+        assert_eq!(aggregated.outlier_runs, vec![2]);
+
+        let layers = &aggregated.profile.layers;
+        assert!((layers[0].forward + layers[0].backward - 2.0).abs() < 1e-9);
+        assert_eq!(layers[0].samples.len(), 3);
+        assert!(layers[0].forward_std > 0.0);
+        assert_eq!(layers[1].forward_std, 0.0);
+        assert_eq!(layers[0].mem_required, 12);
+
+        let statistics = &aggregated.layers[2];
+        assert!((statistics.median - 6.0).abs() < 1e-9);
+        assert!(statistics.confidence_interval > 0.0);
+    }
+
+    #[test]
+    fn test_no_outliers_in_consistent_runs() {
+        let profiles = vec![profile(&[1.0, 2.0], 1), profile(&[1.0, 2.0], 1)];
+        let aggregated = aggregate_profiles(&profiles, Estimator::Median).unwrap();
+        assert!(aggregated.outlier_runs.is_empty());
+        assert_eq!(aggregated.layers[1].mean, 2.0);
+        assert_eq!(aggregated.layers[1].confidence_interval, 0.0);
+    }
+
+    #[test]
+    fn test_return_error_for_different_profiles() {
+        assert!(matches!(
+            aggregate_profiles(&[], Estimator::Mean),
+            Err(ProfileError::NoProfiles)
+        ));
+
+        let mut other = profile(&[1.0, 2.0], 1);
+        other.tp_size = 2;
+        assert!(aggregate_profiles(&[profile(&[1.0, 2.0], 1), other], Estimator::Mean).is_err());
+        assert!(matches!(
+            aggregate_profiles(
+                &[profile(&[1.0, 2.0], 1), profile(&[1.0], 1)],
+                Estimator::Mean
+            ),
+            Err(ProfileError::MetadataMismatch {
+                field: "layers",
+                ..
+            })
+        ));
+    }
+}
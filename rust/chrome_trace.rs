@@ -0,0 +1,369 @@
+use crate::execution_result::LayerExecutionResult;
+use crate::validation::ProfileError;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Time interval of a CPU event on a thread, in microseconds.
+struct Span {
+    thread: String,
+    start: f64,
+    end: f64,
+}
+
+impl Span {
+    fn contains(&self, thread: &str, ts: f64) -> bool {
+        self.thread == thread && self.start <= ts && ts <= self.end
+    }
+}
+
+// A layer scope, i.e. one forward pass of a layer
+struct Scope {
+    span: Span,
+    layer: usize,
+    // Forward passes of the same layer seen before this one
+    occurrence: usize,
+}
+
+// An operator with an autograd sequence number, which its backward operator shares
+struct Operator {
+    span: Span,
+    sequence_number: u64,
+    backward: bool,
+}
+
+fn id_of(value: &Value) -> String {
+    match value {
+        Value::String(id) => id.clone(),
+        Value::Number(id) => id.as_f64().unwrap_or(0.0).to_string(),
+        _ => String::new(),
+    }
+}
+
+fn thread_of(event: &Value) -> String {
+    format!("{}:{}", id_of(&event["pid"]), id_of(&event["tid"]))
+}
+
+fn span_of(event: &Value) -> Option<Span> {
+    let start = event["ts"].as_f64()?;
+    Some(Span {
+        thread: thread_of(event),
+        start,
+        end: start + event["dur"].as_f64()?,
+    })
+}
+
+/// Innermost of the spans containing the given time on the given thread.
+fn innermost<'a, T>(
+    items: &'a [T],
+    span: impl Fn(&T) -> &Span,
+    thread: &str,
+    ts: f64,
+) -> Option<&'a T> {
+    items
+        .iter()
+        .filter(|item| span(item).contains(thread, ts))
+        .min_by(|a, b| {
+            let a = span(a);
+            let b = span(b);
+            (a.end - a.start).total_cmp(&(b.end - b.start))
+        })
+}
+
+/// Layer timings from a `torch.profiler` Chrome trace.
+///
+/// Each forward pass of a layer must be recorded as a CPU scope named after the layer,
+/// e.g. with `record_function(layer_name)`; `nn.Module: ` prefixed names also match.
+/// GPU kernel time is attributed to the layer whose scope launched the kernel. Kernels of
+/// the backward pass are attributed through the autograd sequence number their operator
+/// shares with the forward operator. With several iterations in the trace, timings are
+/// averaged and each iteration becomes a sample. Memory is not part of the trace and is zero.
+pub fn import_chrome_trace(
+    trace: &Value,
+    layer_names: &[String],
+) -> Result<Vec<LayerExecutionResult>, ProfileError> {
+    let invalid = |reason: String| ProfileError::InvalidTrace { reason };
+    let events = match trace {
+        Value::Array(events) => events,
+        _ => trace["traceEvents"]
+            .as_array()
+            .ok_or_else(|| invalid("no traceEvents".to_string()))?,
+    };
+
+    let layer_of: HashMap<&str, usize> = layer_names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| (name.as_str(), index))
+        .collect();
+
+    let mut scopes: Vec<Scope> = Vec::new();
+    let mut operators: Vec<Operator> = Vec::new();
+    // Key: correlation id of the kernel launch. Value: (thread, launch time)
+    let mut launches: HashMap<String, (String, f64)> = HashMap::new();
+    // (correlation id, duration) of GPU kernels
+    let mut kernels: Vec<(String, f64)> = Vec::new();
+    let mut num_occurrences: Vec<usize> = vec![0; layer_names.len()];
+
+    for event in events
+        .iter()
+        .filter(|event| event["ph"].as_str() == Some("X"))
+    {
+        let name = event["name"].as_str().unwrap_or("");
+        let category = event["cat"].as_str().unwrap_or("");
+        let args = &event["args"];
+        match category {
+            "kernel" | "gpu_memcpy" | "gpu_memset" => {
+                if let Some(duration) = event["dur"].as_f64() {
+                    kernels.push((id_of(&args["correlation"]), duration));
+                }
+            }
+            "cuda_runtime" | "cuda_driver" => {
+                if let Some(ts) = event["ts"].as_f64() {
+                    launches.insert(id_of(&args["correlation"]), (thread_of(event), ts));
+                }
+            }
+            // GPU side copies of CPU scopes
+            "gpu_user_annotation" => {}
+            _ => {
+                let layer = layer_of
+                    .get(name)
+                    .or_else(|| layer_of.get(name.trim_start_matches("nn.Module: ")));
+                match (layer, span_of(event)) {
+                    (Some(&layer), Some(span)) => {
+                        scopes.push(Scope {
+                            span,
+                            layer,
+                            occurrence: num_occurrences[layer],
+                        });
+                        num_occurrences[layer] += 1;
+                    }
+                    (None, Some(span)) => {
+                        if let Some(sequence_number) = args["Sequence number"].as_u64() {
+                            operators.push(Operator {
+                                span,
+                                sequence_number,
+                                backward: !args["Fwd thread id"].is_null(),
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if let Some(index) = num_occurrences.iter().position(|&count| count == 0) {
+        return Err(invalid(format!(
+            "layer {} has no scope in the trace",
+            layer_names[index]
+        )));
+    }
+
+    // Key: sequence number of a forward operator. Value: (layer, occurrence)
+    let mut forward_operators: HashMap<u64, (usize, usize)> = HashMap::new();
+    for operator in operators.iter().filter(|operator| !operator.backward) {
+        let midpoint = (operator.span.start + operator.span.end) / 2.0;
+        if let Some(scope) = innermost(
+            &scopes,
+            |scope| &scope.span,
+            &operator.span.thread,
+            midpoint,
+        ) {
+            forward_operators.insert(operator.sequence_number, (scope.layer, scope.occurrence));
+        }
+    }
+    let backward_operators: Vec<&Operator> = operators
+        .iter()
+        .filter(|operator| {
+            operator.backward && forward_operators.contains_key(&operator.sequence_number)
+        })
+        .collect();
+
+    // Kernel time (us) of each layer and occurrence
+    let mut forward: Vec<Vec<f64>> = num_occurrences.iter().map(|&n| vec![0.0; n]).collect();
+    let mut backward = forward.clone();
+    for (correlation, duration) in kernels.iter() {
+        let Some((thread, ts)) = launches.get(correlation) else {
+            continue;
+        };
+        if let Some(scope) = innermost(&scopes, |scope| &scope.span, thread, *ts) {
+            forward[scope.layer][scope.occurrence] += duration;
+        } else if let Some(operator) =
+            innermost(&backward_operators, |operator| &operator.span, thread, *ts)
+        {
+            let (layer, occurrence) = forward_operators[&operator.sequence_number];
+            backward[layer][occurrence] += duration;
+        }
+    }
+
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64 / 1000.0;
+    Ok(layer_names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| LayerExecutionResult {
+            layer_index: index as u32,
+            layer_name: name.clone(),
+            forward: mean(&forward[index]),
+            backward: mean(&backward[index]),
+            samples: if num_occurrences[index] > 1 {
+                forward[index]
+                    .iter()
+                    .zip(backward[index].iter())
+                    .map(|(forward, backward)| (forward + backward) / 1000.0)
+                    .collect()
+            } else {
+                vec![]
+            },
+            ..Default::default()
+        })
+        .collect())
+}
+
+/// Reads a Chrome trace file and imports the timings of the given layers.
+pub fn load_chrome_trace(
+    path: &Path,
+    layer_names: &[String],
+) -> Result<Vec<LayerExecutionResult>, ProfileError> {
+    let unreadable = |reason: String| ProfileError::UnreadableFile {
+        path: path.display().to_string(),
+        reason,
+    };
+    let data = fs::read_to_string(path).map_err(|e| unreadable(e.to_string()))?;
+    let trace: Value = serde_json::from_str(&data).map_err(|e| unreadable(e.to_string()))?;
+    import_chrome_trace(&trace, layer_names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cpu(name: &str, cat: &str, tid: u32, ts: f64, dur: f64, args: &str) -> String {
+        format!(
+            r#"{{"ph": "X", "cat": "{}", "name": "{}", "pid": 1, "tid": {}, "ts": {}, "dur": {}, "args": {{{}}}}}"#,
+            cat, name, tid, ts, dur, args
+        )
+    }
+
+    fn launch(tid: u32, ts: f64, correlation: u32) -> String {
+        cpu(
+            "cudaLaunchKernel",
+            "cuda_runtime",
+            tid,
+            ts,
+            1.0,
+            &format!(r#""correlation": {}"#, correlation),
+        )
+    }
+
+    fn kernel(dur: f64, correlation: u32) -> String {
+        format!(
+            r#"{{"ph": "X", "cat": "kernel", "name": "gemm", "pid": 0, "tid": 7, "ts": 0, "dur": {}, "args": {{"correlation": {}}}}}"#,
+            dur, correlation
+        )
+    }
+
+    // Two layers on the main thread (1), backward on the autograd thread (2)
+    fn trace(num_iterations: u32) -> Value {
+        let mut events = vec![];
+        for iteration in 0..num_iterations {
+            let t = iteration as f64 * 1000.0;
+            let c = iteration * 10;
+            let seq = iteration as u64 * 10;
+            let scale = (iteration + 1) as f64;
+            events.extend([
+                cpu("layer0", "user_annotation", 1, t, 100.0, ""),
+                cpu(
+                    "aten::mm",
+                    "cpu_op",
+                    1,
+                    t + 10.0,
+                    20.0,
+                    &format!(r#""Sequence number": {}"#, seq),
+                ),
+                launch(1, t + 15.0, c),
+                kernel(1000.0 * scale, c),
+                cpu(
+                    "nn.Module: layer1",
+                    "python_function",
+                    1,
+                    t + 200.0,
+                    100.0,
+                    "",
+                ),
+                cpu(
+                    "aten::mm",
+                    "cpu_op",
+                    1,
+                    t + 210.0,
+                    20.0,
+                    &format!(r#""Sequence number": {}"#, seq + 1),
+                ),
+                launch(1, t + 215.0, c + 1),
+                kernel(3000.0, c + 1),
+                cpu(
+                    "autograd::engine::evaluate_function: MmBackward0",
+                    "cpu_op",
+                    2,
+                    t + 500.0,
+                    50.0,
+                    &format!(r#""Sequence number": {}, "Fwd thread id": 1"#, seq + 1),
+                ),
+                launch(2, t + 510.0, c + 2),
+                kernel(6000.0, c + 2),
+                cpu(
+                    "autograd::engine::evaluate_function: MmBackward0",
+                    "cpu_op",
+                    2,
+                    t + 600.0,
+                    50.0,
+                    &format!(r#""Sequence number": {}, "Fwd thread id": 1"#, seq),
+                ),
+                launch(2, t + 610.0, c + 3),
+                kernel(2000.0, c + 3),
+                // An optimizer kernel outside of any layer
+                launch(1, t + 900.0, c + 4),
+                kernel(5000.0, c + 4),
+            ]);
+        }
+        serde_json::from_str(&format!(r#"{{"traceEvents": [{}]}}"#, events.join(", "))).unwrap()
+    }
+
+    fn names() -> Vec<String> {
+        vec!["layer0".to_string(), "layer1".to_string()]
+    }
+
+    #[test]
+    fn test_import_chrome_trace() {
+        let layers = import_chrome_trace(&trace(1), &names()).unwrap();
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].layer_name, "layer0");
+        assert_eq!(layers[0].forward, 1.0);
+        assert_eq!(layers[0].backward, 2.0);
+        assert_eq!(layers[1].layer_index, 1);
+        assert_eq!(layers[1].forward, 3.0);
+        assert_eq!(layers[1].backward, 6.0);
+        assert!(layers[0].samples.is_empty());
+    }
+
+    #[test]
+    fn test_import_chrome_trace_with_several_iterations() {
+        let layers = import_chrome_trace(&trace(2), &names()).unwrap();
+        // Forward kernel of layer0 takes 1ms and 2ms
+        assert_eq!(layers[0].forward, 1.5);
+        assert_eq!(layers[0].backward, 2.0);
+        assert_eq!(layers[0].samples, vec![3.0, 4.0]);
+        assert_eq!(layers[1].samples, vec![9.0, 9.0]);
+    }
+
+    #[test]
+    fn test_return_error_for_layer_missing_in_trace() {
+        let mut names = names();
+        names.push("layer2".to_string());
+        assert!(matches!(
+            import_chrome_trace(&trace(1), &names),
+            Err(ProfileError::InvalidTrace { .. })
+        ));
+        assert!(import_chrome_trace(&Value::Null, &names).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use crate::execution_result::{extract_optional, LayerExecutionResult};
+use crate::memory_model::MemoryModel;
+use crate::schedule::{OneForwardOneBackward, PipelineSchedule};
+use crate::PlannerError;
+use pyo3::conversion::FromPyObject;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Point-to-point link between two adjacent pipeline stages.
+///
+/// Bandwidth is in GB/s and latency in milliseconds, so that transfer times
+/// can be added directly to the profiled layer latencies (also in ms).
+#[derive(Clone, Copy, Debug)]
+pub struct LinkSpec {
+    pub bandwidth: f64,
+    pub latency: f64,
+}
+
+impl LinkSpec {
+    pub fn new(bandwidth: f64, latency: f64) -> Self {
+        LinkSpec { bandwidth, latency }
+    }
+
+    /// Time (ms) to send `bytes` over this link in one direction.
+    pub fn transfer_time(&self, bytes: u64) -> f64 {
+        // GB/s -> bytes/ms
+        let bytes_per_ms = self.bandwidth * 1e6;
+        self.latency + bytes as f64 / bytes_per_ms
+    }
+
+    /// Time (ms) a training microbatch spends crossing this link: activations of
+    /// `bytes` forward and gradients of the same size backward.
+    pub fn round_trip_time(&self, bytes: u64) -> f64 {
+        2.0 * self.transfer_time(bytes)
+    }
+}
+
+impl Default for LinkSpec {
+    /// A link that costs nothing, i.e. stage boundaries are free.
+    fn default() -> Self {
+        LinkSpec {
+            bandwidth: f64::INFINITY,
+            latency: 0.0,
+        }
+    }
+}
+
+/// Range of microbatch counts a pipeline may be assigned per iteration.
+///
+/// The instantiator splits the global batch across pipelines, so a template
+/// is typically used with more than one microbatch count.
+#[derive(Clone, Copy, Debug)]
+pub struct MicrobatchRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl MicrobatchRange {
+    pub fn new(min: u32, max: u32) -> Result<Self, PlannerError> {
+        if min == 0 {
+            return Err(PlannerError::invalid_config(
+                "Number of microbatches must be positive",
+            ));
+        }
+        if min > max {
+            return Err(PlannerError::invalid_config(format!(
+                "Minimum number of microbatches {} is larger than the maximum {}",
+                min, max
+            )));
+        }
+        Ok(MicrobatchRange { min, max })
+    }
+
+    pub fn exact(num_microbatches: u32) -> Result<Self, PlannerError> {
+        MicrobatchRange::new(num_microbatches, num_microbatches)
+    }
+
+    /// Iteration time is linear in the number of microbatches, so ranking templates
+    /// at the mean count is the same as ranking them by average time over the range.
+    pub fn mean(&self) -> f64 {
+        (self.min as f64 + self.max as f64) / 2.0
+    }
+}
+
+impl Default for MicrobatchRange {
+    fn default() -> Self {
+        MicrobatchRange { min: 128, max: 128 }
+    }
+}
+
+/// Device memory available to a single stage.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryBudget {
+    // Device memory capacity in bytes
+    pub capacity: u64,
+    // Fraction of the capacity kept free, e.g. for fragmentation or the CUDA context
+    pub headroom: f64,
+}
+
+impl MemoryBudget {
+    pub fn new(capacity: u64, headroom: f64) -> Result<Self, PlannerError> {
+        Self::validate_headroom(headroom)?;
+        Ok(MemoryBudget { capacity, headroom })
+    }
+
+    /// Headroom must leave some of the capacity usable.
+    pub fn validate_headroom(headroom: f64) -> Result<(), PlannerError> {
+        if !(0.0..1.0).contains(&headroom) {
+            return Err(PlannerError::invalid_config(format!(
+                "Memory headroom must be in [0, 1), got {}",
+                headroom
+            )));
+        }
+        Ok(())
+    }
+
+    /// Bytes a stage may use.
+    pub fn usable(&self) -> u64 {
+        (self.capacity as f64 * (1.0 - self.headroom)) as u64
+    }
+}
+
+/// Statistic of stage latencies templates are planned for.
+///
+/// Stage latencies are taken as normally distributed, being sums of independent layer
+/// latencies. Planning every stage at a percentile overestimates the same percentile of the
+/// iteration time, but favors templates whose bottleneck is not a noisy stage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LatencyStatistic {
+    Mean,
+    P90,
+    P95,
+    P99,
+}
+
+impl LatencyStatistic {
+    pub fn from_name(name: &str) -> Result<Self, PlannerError> {
+        match name.to_lowercase().as_str() {
+            "mean" => Ok(LatencyStatistic::Mean),
+            "p90" => Ok(LatencyStatistic::P90),
+            "p95" => Ok(LatencyStatistic::P95),
+            "p99" => Ok(LatencyStatistic::P99),
+            _ => Err(PlannerError::invalid_config(format!(
+                "Unknown latency statistic: {}",
+                name
+            ))),
+        }
+    }
+
+    /// Quantile of the standard normal distribution.
+    pub fn z_score(&self) -> f64 {
+        match self {
+            LatencyStatistic::Mean => 0.0,
+            LatencyStatistic::P90 => 1.2816,
+            LatencyStatistic::P95 => 1.6449,
+            LatencyStatistic::P99 => 2.3263,
+        }
+    }
+}
+
+/// Which pipelines the planner keeps for each subproblem.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchMode {
+    // Only the fastest pipeline
+    Best,
+    // Every pipeline that no other pipeline beats in both latency and peak stage memory.
+    // Subproblems are pruned before their memory is final, so the frontier is approximate.
+    ParetoFrontier,
+    // The given number of fastest distinct pipelines
+    TopK(usize),
+}
+
+/// A kind of accelerator in a heterogeneous cluster, e.g. A100 or H100 nodes.
+#[derive(Clone)]
+pub struct DeviceClass {
+    pub name: String,
+    // Throughput relative to the device the profile was taken on.
+    // Layer latencies are divided by this factor.
+    pub speed: f64,
+    // Memory of a device of this class. Falls back to PlannerConfig::memory_budget if None.
+    pub memory_budget: Option<MemoryBudget>,
+    // Profile taken on this class itself. Used instead of scaling the shared profile.
+    pub profile: Option<Vec<LayerExecutionResult>>,
+}
+
+impl DeviceClass {
+    pub fn new(name: &str, speed: f64) -> Self {
+        DeviceClass {
+            name: name.to_string(),
+            speed,
+            memory_budget: None,
+            profile: None,
+        }
+    }
+}
+
+impl Default for DeviceClass {
+    /// The device the shared profile was taken on.
+    fn default() -> Self {
+        DeviceClass::new("default", 1.0)
+    }
+}
+
+impl<'source> FromPyObject<'source> for DeviceClass {
+    fn extract(ob: &'source pyo3::PyAny) -> pyo3::PyResult<Self> {
+        let name: String = ob.getattr("name")?.extract()?;
+        let speed: Option<f64> = extract_optional(ob, "speed")?;
+        let memory: Option<u64> = extract_optional(ob, "memory")?;
+        let profile: Option<Vec<LayerExecutionResult>> = extract_optional(ob, "profile")?;
+        Ok(DeviceClass {
+            name,
+            speed: speed.unwrap_or(1.0),
+            memory_budget: memory.map(|capacity| MemoryBudget {
+                capacity,
+                headroom: 0.0,
+            }),
+            profile,
+        })
+    }
+}
+
+/// Stops planning once the token is set or the timeout has passed since planning started.
+///
+/// Planning stops between subproblems, so that pipelines planned before are kept.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    // Set by whoever made the planning obsolete, e.g. a reconfiguration
+    pub token: Arc<AtomicBool>,
+    pub timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl Cancellation {
+    pub fn new(token: Arc<AtomicBool>, timeout: Option<Duration>) -> Self {
+        Cancellation {
+            token,
+            timeout,
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+        }
+    }
+
+    pub fn check(&self) -> Result<(), PlannerError> {
+        if self.token.load(Ordering::Relaxed) {
+            return Err(PlannerError::Cancelled(
+                "the cancellation token was set".to_string(),
+            ));
+        }
+        match (self.deadline, self.timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => Err(
+                PlannerError::Cancelled(format!("timed out after {:?}", timeout)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PlannerConfig {
+    // Link used for activation/gradient transfer between adjacent stages on different nodes
+    pub link: LinkSpec,
+    // Link between adjacent stages on the same node, e.g. NVLink
+    pub intra_node_link: LinkSpec,
+    // Number of GPUs per node, each running one stage
+    pub gpus_per_node: u32,
+    // Number of microbatches templates are ranked with
+    pub num_microbatches: MicrobatchRange,
+    // Schedule the pipelines are executed with
+    pub schedule: Arc<dyn PipelineSchedule>,
+    // Statistic of noisy stage latencies templates are ranked with
+    pub latency_statistic: LatencyStatistic,
+    // Stages that do not fit in this budget are infeasible. Unlimited if None.
+    pub memory_budget: Option<MemoryBudget>,
+    // Overrides profiled static memory of layers with parameter counts
+    pub memory_model: Option<MemoryModel>,
+    // Pipelines kept per subproblem and returned per number of nodes
+    pub search: SearchMode,
+    // Device classes nodes may belong to. Empty if all nodes are the profiled device.
+    pub device_classes: Vec<DeviceClass>,
+    // Checked while planning. Never cancels by default.
+    pub cancellation: Cancellation,
+}
+
+impl Default for PlannerConfig {
+    fn default() -> Self {
+        PlannerConfig {
+            link: LinkSpec::default(),
+            intra_node_link: LinkSpec::default(),
+            gpus_per_node: 1,
+            num_microbatches: MicrobatchRange::default(),
+            schedule: Arc::new(OneForwardOneBackward),
+            latency_statistic: LatencyStatistic::Mean,
+            memory_budget: None,
+            memory_model: None,
+            search: SearchMode::Best,
+            device_classes: vec![],
+            cancellation: Cancellation::default(),
+        }
+    }
+}
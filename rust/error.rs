@@ -0,0 +1,217 @@
+use crate::validation::ProfileError;
+use pyo3::prelude::*;
+use std::fmt;
+
+/// Why planning failed. Each variant is raised as its own exception class in Python.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlannerError {
+    // No pipeline template exists for the number of nodes of each device class
+    InfeasibleNodeCount {
+        num_nodes: Vec<u32>,
+        reason: String,
+    },
+    // A stage does not fit in device memory even with activation checkpointing
+    MemoryExceeded {
+        num_nodes: Vec<u32>,
+        layers: (u32, u32),
+        required: u64,
+        budget: u64,
+    },
+    InvalidProfile(ProfileError),
+    // Planner options or device classes that cannot be planned with
+    InvalidConfig(String),
+    // Planning was cancelled or timed out before it finished
+    Cancelled(String),
+}
+
+impl PlannerError {
+    pub fn invalid_config(message: impl Into<String>) -> Self {
+        PlannerError::InvalidConfig(message.into())
+    }
+}
+
+fn describe_nodes(num_nodes: &[u32]) -> String {
+    match num_nodes {
+        [num_nodes] => num_nodes.to_string(),
+        _ => format!("{:?}", num_nodes),
+    }
+}
+
+impl fmt::Display for PlannerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlannerError::InfeasibleNodeCount { num_nodes, reason } => write!(
+                f,
+                "No feasible pipeline template for {} nodes: {}",
+                describe_nodes(num_nodes),
+                reason
+            ),
+            PlannerError::MemoryExceeded {
+                num_nodes,
+                layers,
+                required,
+                budget,
+            } => write!(
+                f,
+                "No feasible pipeline template for {} nodes: stage with layers {}..{} requires {} bytes, exceeding the memory budget of {} bytes",
+                describe_nodes(num_nodes),
+                layers.0,
+                layers.1,
+                required,
+                budget
+            ),
+            PlannerError::InvalidProfile(error) => write!(f, "Invalid profile: {}", error),
+            PlannerError::InvalidConfig(message) => write!(f, "Invalid configuration: {}", message),
+            PlannerError::Cancelled(reason) => write!(f, "Planning cancelled: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PlannerError {}
+
+impl From<ProfileError> for PlannerError {
+    fn from(error: ProfileError) -> PlannerError {
+        PlannerError::InvalidProfile(error)
+    }
+}
+
+impl From<ProfileError> for PyErr {
+    fn from(error: ProfileError) -> PyErr {
+        PlannerError::InvalidProfile(error).into()
+    }
+}
+
+/// Exception classes of the `planner` module, all subclasses of `PlannerError`.
+pub mod exceptions {
+    use pyo3::create_exception;
+
+    create_exception!(planner, PlannerError, pyo3::exceptions::PyRuntimeError);
+    create_exception!(planner, InfeasibleNodeCountError, PlannerError);
+    create_exception!(planner, MemoryExceededError, PlannerError);
+    create_exception!(planner, InvalidProfileError, PlannerError);
+    create_exception!(planner, InvalidConfigError, PlannerError);
+    create_exception!(planner, CancelledError, PlannerError);
+}
+
+/// Sets the fields of the error as attributes of the exception.
+fn set_attributes(py: Python<'_>, error: &PlannerError, exception: &PyErr) -> PyResult<()> {
+    let value = exception.value_bound(py);
+    match error {
+        PlannerError::InfeasibleNodeCount { num_nodes, reason } => {
+            value.setattr("num_nodes", num_nodes.clone())?;
+            value.setattr("reason", reason.clone())?;
+        }
+        PlannerError::MemoryExceeded {
+            num_nodes,
+            layers,
+            required,
+            budget,
+        } => {
+            value.setattr("num_nodes", num_nodes.clone())?;
+            value.setattr("layers", *layers)?;
+            value.setattr("required", *required)?;
+            value.setattr("budget", *budget)?;
+            value.setattr("reason", error.to_string())?;
+        }
+        PlannerError::InvalidProfile(profile_error) => {
+            let layer_index = match profile_error {
+                ProfileError::DuplicateLayerIndex { index } => Some(*index),
+                ProfileError::NonContiguousLayerIndex { index, .. } => Some(*index),
+                ProfileError::InvalidValue { layer_index, .. } => Some(*layer_index),
+                _ => None,
+            };
+            value.setattr("layer_index", layer_index)?;
+            value.setattr("reason", profile_error.to_string())?;
+        }
+        PlannerError::InvalidConfig(message) | PlannerError::Cancelled(message) => {
+            value.setattr("reason", message.clone())?;
+        }
+    }
+    Ok(())
+}
+
+impl From<PlannerError> for PyErr {
+    fn from(error: PlannerError) -> PyErr {
+        let message = error.to_string();
+        let exception = match error {
+            PlannerError::InfeasibleNodeCount { .. } => {
+                exceptions::InfeasibleNodeCountError::new_err(message)
+            }
+            PlannerError::MemoryExceeded { .. } => {
+                exceptions::MemoryExceededError::new_err(message)
+            }
+            PlannerError::InvalidProfile(_) => exceptions::InvalidProfileError::new_err(message),
+            PlannerError::InvalidConfig(_) => exceptions::InvalidConfigError::new_err(message),
+            PlannerError::Cancelled(_) => exceptions::CancelledError::new_err(message),
+        };
+        Python::with_gil(|py| match set_attributes(py, &error, &exception) {
+            Ok(()) => exception,
+            Err(e) => e,
+        })
+    }
+}
+
+/// Adds the exception classes to the `planner` module.
+pub fn add_exceptions(py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add(
+        "PlannerError",
+        py.get_type_bound::<exceptions::PlannerError>(),
+    )?;
+    m.add(
+        "InfeasibleNodeCountError",
+        py.get_type_bound::<exceptions::InfeasibleNodeCountError>(),
+    )?;
+    m.add(
+        "MemoryExceededError",
+        py.get_type_bound::<exceptions::MemoryExceededError>(),
+    )?;
+    m.add(
+        "InvalidProfileError",
+        py.get_type_bound::<exceptions::InvalidProfileError>(),
+    )?;
+    m.add(
+        "InvalidConfigError",
+        py.get_type_bound::<exceptions::InvalidConfigError>(),
+    )?;
+    m.add(
+        "CancelledError",
+        py.get_type_bound::<exceptions::CancelledError>(),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_messages() {
+        let error = PlannerError::InfeasibleNodeCount {
+            num_nodes: vec![3],
+            reason: "too few layers".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "No feasible pipeline template for 3 nodes: too few layers"
+        );
+
+        let error = PlannerError::MemoryExceeded {
+            num_nodes: vec![0, 1],
+            layers: (2, 4),
+            required: 8,
+            budget: 5,
+        };
+        assert!(error
+            .to_string()
+            .starts_with("No feasible pipeline template for [0, 1] nodes"));
+        assert!(error
+            .to_string()
+            .contains("exceeding the memory budget of 5 bytes"));
+
+        let error: PlannerError = ProfileError::EmptyProfile.into();
+        assert_eq!(
+            error,
+            PlannerError::InvalidProfile(ProfileError::EmptyProfile)
+        );
+    }
+}
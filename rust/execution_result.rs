@@ -1,16 +1,23 @@
+use crate::config::PlannerConfig;
+use crate::memory_model::Precision;
+use crate::schedule::{LatencySummary, PipelineSchedule};
+use crate::validation::{validate_profile, ProfileError};
 use pyo3::conversion::FromPyObject;
 use serde::{Deserialize, Serialize};
 use std::clone::Clone;
 use std::cmp::{Ordering, PartialEq};
+use std::fs;
+use std::path::Path;
 use std::sync::Arc;
 
+/// Profile of a model as written by the profiler, with what it was taken for.
 #[derive(Serialize, Deserialize)]
 pub struct ProfileResult {
-    model_name: String,
-    microbatch_size: u32,
-    tp_size: u32,
-    precision: String,
-    layers: Vec<LayerExecutionResult>,
+    pub model_name: String,
+    pub microbatch_size: u32,
+    pub tp_size: u32,
+    pub precision: String,
+    pub layers: Vec<LayerExecutionResult>,
 }
 
 impl ProfileResult {
@@ -29,15 +36,185 @@ impl ProfileResult {
             layers,
         }
     }
+
+    /// Reads and validates a profile file.
+    pub fn load(path: &Path) -> Result<Self, ProfileError> {
+        let unreadable = |reason: String| ProfileError::UnreadableFile {
+            path: path.display().to_string(),
+            reason,
+        };
+        let data = fs::read_to_string(path).map_err(|e| unreadable(e.to_string()))?;
+        let profile: ProfileResult =
+            serde_json::from_str(&data).map_err(|e| unreadable(e.to_string()))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ProfileError> {
+        self.validate()?;
+        let unwritable = |reason: String| ProfileError::UnreadableFile {
+            path: path.display().to_string(),
+            reason,
+        };
+        let data = serde_json::to_string_pretty(self).map_err(|e| unwritable(e.to_string()))?;
+        fs::write(path, data).map_err(|e| unwritable(e.to_string()))
+    }
+
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        if self.tp_size == 0 {
+            return Err(ProfileError::InvalidMetadata {
+                field: "tp_size",
+                reason: "must be positive".to_string(),
+            });
+        }
+        if self.microbatch_size == 0 {
+            return Err(ProfileError::InvalidMetadata {
+                field: "microbatch_size",
+                reason: "must be positive".to_string(),
+            });
+        }
+        validate_profile(&self.layers)
+    }
+
+    /// Checks that the profile was taken for what is planned.
+    /// Metadata that is not given is not checked.
+    pub fn check_metadata(
+        &self,
+        model_name: &str,
+        tp_size: Option<u32>,
+        microbatch_size: Option<u32>,
+        precision: Option<Precision>,
+    ) -> Result<(), ProfileError> {
+        let mismatch = |field: &'static str, expected: String, found: String| {
+            Err(ProfileError::MetadataMismatch {
+                field,
+                expected,
+                found,
+            })
+        };
+        if self.model_name != model_name {
+            return mismatch(
+                "model_name",
+                model_name.to_string(),
+                self.model_name.clone(),
+            );
+        }
+        if let Some(tp_size) = tp_size.filter(|&tp_size| tp_size != self.tp_size) {
+            return mismatch("tp_size", tp_size.to_string(), self.tp_size.to_string());
+        }
+        if let Some(microbatch_size) =
+            microbatch_size.filter(|&microbatch_size| microbatch_size != self.microbatch_size)
+        {
+            return mismatch(
+                "microbatch_size",
+                microbatch_size.to_string(),
+                self.microbatch_size.to_string(),
+            );
+        }
+        if let Some(precision) = precision {
+            if Precision::from_name(&self.precision).ok() != Some(precision) {
+                return mismatch(
+                    "precision",
+                    format!("{:?}", precision).to_lowercase(),
+                    self.precision.clone(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that both profiles are of the same layers, parallelism and precision,
+    /// possibly with different microbatch sizes.
+    pub fn check_same_layers(&self, other: &ProfileResult) -> Result<(), ProfileError> {
+        other.check_metadata(self.model_name.as_str(), Some(self.tp_size), None, None)?;
+        if other.precision != self.precision {
+            return Err(ProfileError::MetadataMismatch {
+                field: "precision",
+                expected: self.precision.clone(),
+                found: other.precision.clone(),
+            });
+        }
+        let names = |profile: &ProfileResult| {
+            profile
+                .layers
+                .iter()
+                .map(|layer| layer.layer_name.clone())
+                .collect::<Vec<String>>()
+        };
+        if names(other) != names(self) {
+            return Err(ProfileError::MetadataMismatch {
+                field: "layers",
+                expected: names(self).join(", "),
+                found: names(other).join(", "),
+            });
+        }
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct LayerExecutionResult {
     pub layer_index: u32,
     pub layer_name: String,
     pub forward: f64,
     pub backward: f64,
     pub mem_required: u64,
+    // Size (bytes) of the layer output sent to the next stage per microbatch
+    #[serde(default)]
+    pub activation_size: u64,
+    // Breakdown of mem_required (bytes). Both are zero if the profile has no breakdown.
+    // Parameters, gradients and optimizer states, independent of microbatches
+    #[serde(default)]
+    pub param_mem: u64,
+    // Activations stashed for the backward pass of one microbatch
+    #[serde(default)]
+    pub activation_mem: u64,
+    // Number of parameters of the layer (per tensor parallel rank)
+    #[serde(default)]
+    pub num_parameters: u64,
+    // Time (ms) to recompute the forward pass during backward if checkpointed
+    #[serde(default)]
+    pub recompute: f64,
+    // Activation memory (bytes) per microbatch that checkpointing frees
+    #[serde(default)]
+    pub checkpoint_mem_saved: u64,
+    // Standard deviation (ms) of forward and backward latencies
+    #[serde(default)]
+    pub forward_std: f64,
+    #[serde(default)]
+    pub backward_std: f64,
+    // Measured forward + backward latencies (ms). Used if no standard deviation is given.
+    #[serde(default)]
+    pub samples: Vec<f64>,
+}
+
+impl LayerExecutionResult {
+    /// Variance of forward + backward latency, assuming both are independent.
+    pub fn latency_variance(&self) -> f64 {
+        if self.forward_std > 0.0 || self.backward_std > 0.0 || self.samples.len() < 2 {
+            return self.forward_std.powi(2) + self.backward_std.powi(2);
+        }
+
+        let n = self.samples.len() as f64;
+        let mean = self.samples.iter().sum::<f64>() / n;
+        self.samples
+            .iter()
+            .map(|sample| (sample - mean).powi(2))
+            .sum::<f64>()
+            / (n - 1.0)
+    }
+}
+
+/// Extracts an attribute that older profiles may not have.
+pub fn extract_optional<'source, T: FromPyObject<'source> + Default>(
+    ob: &'source pyo3::PyAny,
+    name: &str,
+) -> pyo3::PyResult<T> {
+    if ob.hasattr(name)? {
+        ob.getattr(name)?.extract()
+    } else {
+        Ok(T::default())
+    }
 }
 
 impl<'source> FromPyObject<'source> for LayerExecutionResult {
@@ -47,12 +224,30 @@ impl<'source> FromPyObject<'source> for LayerExecutionResult {
         let forward: f64 = ob.getattr("forward")?.extract()?;
         let backward: f64 = ob.getattr("backward")?.extract()?;
         let mem_required: u64 = ob.getattr("mem_required")?.extract()?;
+        let activation_size: u64 = extract_optional(ob, "activation_size")?;
+        let param_mem: u64 = extract_optional(ob, "param_mem")?;
+        let activation_mem: u64 = extract_optional(ob, "activation_mem")?;
+        let num_parameters: u64 = extract_optional(ob, "num_parameters")?;
+        let recompute: f64 = extract_optional(ob, "recompute")?;
+        let checkpoint_mem_saved: u64 = extract_optional(ob, "checkpoint_mem_saved")?;
+        let forward_std: f64 = extract_optional(ob, "forward_std")?;
+        let backward_std: f64 = extract_optional(ob, "backward_std")?;
+        let samples: Vec<f64> = extract_optional(ob, "samples")?;
         Ok(LayerExecutionResult {
             layer_index,
             layer_name,
             forward,
             backward,
             mem_required,
+            activation_size,
+            param_mem,
+            activation_mem,
+            num_parameters,
+            recompute,
+            checkpoint_mem_saved,
+            forward_std,
+            backward_std,
+            samples,
         })
     }
 }
@@ -61,40 +256,158 @@ pub struct StageExecutionResult {
     pub layers: (u32, u32),
     forward: f64,
     backward: f64,
-    mem_required: u64,
+    param_mem: u64,
+    activation_mem: u64,
+    // Size (bytes) of the activations sent to the next stage
+    pub output_activation_size: u64,
+    recompute: f64,
+    checkpoint_mem_saved: u64,
+    // Variance of the stage latency, layers being independent
+    latency_variance: f64,
+    // Latency (ms) planned on top of the mean to cover latency jitter
+    margin: f64,
+    // Whether activation checkpointing is enabled for this stage
+    pub checkpointed: bool,
+    // Index of the device class this stage runs on
+    pub device_class: usize,
 }
 
 impl StageExecutionResult {
-    pub fn new(layers: &[LayerExecutionResult]) -> Self {
-        let mut forward = 0.0;
-        let mut backward = 0.0;
-        let mut mem_required = 0;
+    /// The same stage on the given device class, `speed` times as fast as the profiled device.
+    pub fn on_device(self, device_class: usize, speed: f64) -> Self {
+        StageExecutionResult {
+            forward: self.forward / speed,
+            backward: self.backward / speed,
+            recompute: self.recompute / speed,
+            latency_variance: self.latency_variance / speed.powi(2),
+            margin: self.margin / speed,
+            device_class,
+            ..self
+        }
+    }
 
-        for layer in layers {
-            forward += layer.forward;
-            backward += layer.backward;
-            mem_required += layer.mem_required;
+    /// The same stage planned with its latency `z_score` standard deviations above the mean.
+    pub fn with_margin(self, z_score: f64) -> Self {
+        StageExecutionResult {
+            margin: z_score * self.latency_variance.sqrt(),
+            ..self
         }
+    }
+
+    /// Whether enabling activation checkpointing would reduce memory of this stage.
+    pub fn can_checkpoint(&self) -> bool {
+        !self.checkpointed && self.checkpoint_mem_saved > 0
+    }
 
+    /// The same stage with activation checkpointing enabled,
+    /// trading recomputation in the backward pass for activation memory.
+    pub fn with_checkpointing(&self) -> Self {
         StageExecutionResult {
-            layers: (
-                layers[0].layer_index,
-                layers[layers.len() - 1].layer_index + 1,
-            ),
-            forward,
-            backward,
-            mem_required,
+            layers: self.layers,
+            forward: self.forward,
+            backward: self.backward + self.recompute,
+            param_mem: self.param_mem,
+            activation_mem: self
+                .activation_mem
+                .saturating_sub(self.checkpoint_mem_saved),
+            output_activation_size: self.output_activation_size,
+            recompute: self.recompute,
+            checkpoint_mem_saved: self.checkpoint_mem_saved,
+            latency_variance: self.latency_variance,
+            margin: self.margin,
+            checkpointed: true,
+            device_class: self.device_class,
         }
     }
 
+    /// Latency (ms) of the stage per microbatch, including the planned margin for jitter.
     pub fn latency(&self) -> f64 {
-        self.forward + self.backward
+        self.forward + self.backward + self.margin
+    }
+
+    /// Peak memory (bytes) of this stage when it holds activations of `in_flight` microbatches.
+    pub fn peak_memory(&self, in_flight: f64) -> u64 {
+        self.param_mem + (self.activation_mem as f64 * in_flight).ceil() as u64
+    }
+}
+
+/// Prefix sums of layer costs, giving the stage of any range of layers in constant time.
+pub struct StageCosts {
+    // Entry i sums layers 0..i
+    forward: Vec<f64>,
+    backward: Vec<f64>,
+    recompute: Vec<f64>,
+    latency_variance: Vec<f64>,
+    param_mem: Vec<u64>,
+    activation_mem: Vec<u64>,
+    checkpoint_mem_saved: Vec<u64>,
+    // Entry i is the size of the output of layer i
+    activation_size: Vec<u64>,
+}
+
+impl StageCosts {
+    pub fn new(layers: &[LayerExecutionResult]) -> Self {
+        fn prefix_sums<T: Copy + std::ops::Add<Output = T>>(
+            zero: T,
+            values: impl Iterator<Item = T>,
+        ) -> Vec<T> {
+            let mut sums = vec![zero];
+            for value in values {
+                sums.push(sums[sums.len() - 1] + value);
+            }
+            sums
+        }
+        // Without a breakdown, the whole footprint is taken as static memory
+        let breakdown = |layer: &LayerExecutionResult| {
+            if layer.param_mem == 0 && layer.activation_mem == 0 {
+                (layer.mem_required, 0)
+            } else {
+                (layer.param_mem, layer.activation_mem)
+            }
+        };
+
+        StageCosts {
+            forward: prefix_sums(0.0, layers.iter().map(|layer| layer.forward)),
+            backward: prefix_sums(0.0, layers.iter().map(|layer| layer.backward)),
+            recompute: prefix_sums(0.0, layers.iter().map(|layer| layer.recompute)),
+            latency_variance: prefix_sums(0.0, layers.iter().map(|layer| layer.latency_variance())),
+            param_mem: prefix_sums(0, layers.iter().map(|layer| breakdown(layer).0)),
+            activation_mem: prefix_sums(0, layers.iter().map(|layer| breakdown(layer).1)),
+            checkpoint_mem_saved: prefix_sums(
+                0,
+                layers.iter().map(|layer| layer.checkpoint_mem_saved),
+            ),
+            activation_size: layers.iter().map(|layer| layer.activation_size).collect(),
+        }
+    }
+
+    /// Stage of layers `i..j`.
+    pub fn stage(&self, i: usize, j: usize) -> StageExecutionResult {
+        StageExecutionResult {
+            layers: (i as u32, j as u32),
+            forward: self.forward[j] - self.forward[i],
+            backward: self.backward[j] - self.backward[i],
+            param_mem: self.param_mem[j] - self.param_mem[i],
+            activation_mem: self.activation_mem[j] - self.activation_mem[i],
+            output_activation_size: self.activation_size[j - 1],
+            recompute: self.recompute[j] - self.recompute[i],
+            checkpoint_mem_saved: self.checkpoint_mem_saved[j] - self.checkpoint_mem_saved[i],
+            // Clamped as cancellation may leave a tiny negative variance
+            latency_variance: (self.latency_variance[j] - self.latency_variance[i]).max(0.0),
+            margin: 0.0,
+            checkpointed: false,
+            device_class: 0,
+        }
     }
 }
 
 #[derive(Clone)]
 pub struct PipelineExecutionResult {
     pub stages: Vec<Arc<StageExecutionResult>>,
+    // Per-stage p2p time (ms) spent sending activations and gradients per microbatch
+    pub comm: Vec<f64>,
+    // Per-stage peak memory (bytes) under the schedule
+    pub stage_memory: Vec<u64>,
     pub t1: f64,
     pub t2: f64,
     pub t3: f64,
@@ -102,60 +415,90 @@ pub struct PipelineExecutionResult {
 }
 
 impl PipelineExecutionResult {
-    pub fn new(left: &PipelineExecutionResult, right: &PipelineExecutionResult) -> Self {
-        let mut stages = left.stages.clone();
-        stages.extend(right.stages.clone());
-
-        let t1 = left.t1 + right.t1;
-
-        let kstar = if left.stages[left.kstar].latency() > right.stages[right.kstar].latency() {
-            left.kstar
-        } else {
-            left.stages.len() + right.kstar
-        };
-
-        let num_microbatches = 4 * stages.len();
-        let t2 = (num_microbatches - stages.len() + kstar - 1) as f64 * stages[kstar].latency();
+    /// Builds a pipeline from its stages and their p2p communication time.
+    pub fn from_stages(
+        stages: Vec<Arc<StageExecutionResult>>,
+        comm: Vec<f64>,
+        config: &PlannerConfig,
+    ) -> Self {
+        let stage_latencies: Vec<f64> = (0..stages.len())
+            .map(|k| stages[k].latency() + comm[k])
+            .collect();
+        let kstar = Self::slowest_stage(&stage_latencies);
+        let (t1, t2, t3) = config.schedule.iteration_time(
+            &LatencySummary::new(&stage_latencies, kstar),
+            config.num_microbatches.mean(),
+        );
 
-        let t3 = if kstar == left.kstar {
-            left.t3 + right.t1
-        } else {
-            right.t3
-        };
+        let stage_memory = Self::peak_memory_per_stage(&stages, config);
 
         PipelineExecutionResult {
             stages,
+            comm,
+            stage_memory,
             t1,
             t2,
             t3,
             kstar,
         }
     }
-    pub fn make_base_result(stage: Arc<StageExecutionResult>) -> Self {
-        let latency = stage.latency();
-        PipelineExecutionResult {
-            stages: vec![stage],
-            t1: latency,
-            t2: 2.0 * (latency),
-            t3: latency,
-            kstar: 0,
-        }
-    }
-    pub fn latency_with_mb(&self, mb: u32) -> f64 {
-        // Because latency is determined by the number of microbatches (4*num_stages),
-        // It is not fair to use the latency as it is. Instead, we calculate the latency
-        // with respect to the same number of microbatches.
-        self.t1
-            + self.t2
-            + self.t3
-            + (((mb as i32) - (4 * self.stages.len() as i32)) as f64)
-                * self.stages[self.kstar].latency()
+    fn peak_memory_per_stage(
+        stages: &[Arc<StageExecutionResult>],
+        config: &PlannerConfig,
+    ) -> Vec<u64> {
+        // Memory must fit with the largest number of microbatches the pipeline may run
+        (0..stages.len())
+            .map(|k| {
+                stages[k].peak_memory(config.schedule.in_flight_microbatches(
+                    k,
+                    stages.len(),
+                    config.num_microbatches.max,
+                ))
+            })
+            .collect()
+    }
+    /// Index of the slowest stage. Ties are broken towards the later stage.
+    fn slowest_stage(stage_latencies: &[f64]) -> usize {
+        let mut kstar = 0;
+        for k in 1..stage_latencies.len() {
+            if stage_latencies[k] >= stage_latencies[kstar] {
+                kstar = k;
+            }
+        }
+        kstar
+    }
+    /// Latency of the given stage per microbatch, including p2p communication.
+    pub fn stage_latency(&self, index: usize) -> f64 {
+        self.stages[index].latency() + self.comm[index]
+    }
+    /// Iteration time with exactly `mb` microbatches under the given schedule.
+    pub fn latency_with_mb(&self, mb: u32, schedule: &dyn PipelineSchedule) -> f64 {
+        let stage_latencies: Vec<f64> = (0..self.stages.len())
+            .map(|k| self.stage_latency(k))
+            .collect();
+        let (t1, t2, t3) = schedule.iteration_time(
+            &LatencySummary::new(&stage_latencies, self.kstar),
+            mb as f64,
+        );
+        t1 + t2 + t3
     }
     pub fn latency(&self) -> f64 {
         self.t1 + self.t2 + self.t3
     }
     pub fn mem_required(&self) -> u64 {
-        self.stages.iter().fold(0, |acc, x| acc + x.mem_required)
+        self.stage_memory.iter().sum()
+    }
+    /// Memory (bytes) of the stage that needs the most.
+    pub fn peak_memory(&self) -> u64 {
+        self.stage_memory.iter().copied().max().unwrap_or(0)
+    }
+    /// Indices of the stages on each node, with `gpus_per_node` consecutive stages per node.
+    pub fn stages_per_node(&self, gpus_per_node: u32) -> Vec<Vec<usize>> {
+        (0..self.stages.len())
+            .collect::<Vec<usize>>()
+            .chunks(gpus_per_node as usize)
+            .map(|stages| stages.to_vec())
+            .collect()
     }
     pub fn get_modules_per_stage(&self, layers: &Vec<LayerExecutionResult>) -> Vec<Vec<String>> {
         let mut modules_per_stage: Vec<Vec<String>> = Vec::new();
@@ -172,8 +515,7 @@ impl PipelineExecutionResult {
 
 impl PartialEq for PipelineExecutionResult {
     fn eq(&self, other: &Self) -> bool {
-        self.latency_with_mb(128) == other.latency_with_mb(128)
-            && self.mem_required() == other.mem_required()
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -181,21 +523,9 @@ impl Eq for PipelineExecutionResult {}
 
 impl Ord for PipelineExecutionResult {
     fn cmp(&self, other: &Self) -> Ordering {
-        if self == other {
-            Ordering::Equal
-        } else {
-            if self.latency_with_mb(128) < other.latency_with_mb(128) {
-                Ordering::Less
-            } else if self.latency_with_mb(128) > other.latency_with_mb(128) {
-                Ordering::Greater
-            } else {
-                if self.mem_required() < other.mem_required() {
-                    Ordering::Less
-                } else {
-                    Ordering::Greater
-                }
-            }
-        }
+        self.latency()
+            .total_cmp(&other.latency())
+            .then(self.mem_required().cmp(&other.mem_required()))
     }
 }
 
@@ -204,3 +534,94 @@ impl PartialOrd for PipelineExecutionResult {
         Some(self.cmp(other))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profile() -> ProfileResult {
+        let layers = (0..3)
+            .map(|i| LayerExecutionResult {
+                layer_index: i,
+                layer_name: format!("layer{}", i),
+                forward: 1.0,
+                backward: 2.0,
+                mem_required: 4,
+                ..Default::default()
+            })
+            .collect();
+        ProfileResult::new("gpt2".to_string(), 2, 1, "bf16".to_string(), layers)
+    }
+
+    #[test]
+    fn test_save_and_load_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile_tp1_mb2_bf16.json");
+        profile().save(&path).unwrap();
+
+        let loaded = ProfileResult::load(&path).unwrap();
+        assert_eq!(loaded.model_name, "gpt2");
+        assert_eq!(loaded.microbatch_size, 2);
+        assert_eq!(loaded.layers.len(), 3);
+        assert_eq!(loaded.layers[2].backward, 2.0);
+
+        assert!(matches!(
+            ProfileResult::load(&dir.path().join("missing.json")),
+            Err(ProfileError::UnreadableFile { .. })
+        ));
+    }
+
+    #[test]
+    fn test_stage_costs_from_prefix_sums() {
+        let mut layers = profile().layers;
+        layers[1].param_mem = 3;
+        layers[1].activation_mem = 2;
+        layers[2].activation_size = 7;
+        let costs = StageCosts::new(&layers);
+
+        let stage = costs.stage(1, 3);
+        assert_eq!(stage.layers, (1, 3));
+        assert_eq!(stage.latency(), 6.0);
+        // Layer 2 has no memory breakdown and counts as static memory
+        assert_eq!(stage.peak_memory(2.0), 3 + 4 + 2 * 2);
+        assert_eq!(stage.output_activation_size, 7);
+        assert_eq!(costs.stage(0, 3).latency(), 9.0);
+    }
+
+    #[test]
+    fn test_check_profile_metadata() {
+        let profile = profile();
+        assert_eq!(
+            profile.check_metadata("gpt2", Some(1), None, Some(Precision::Bf16)),
+            Ok(())
+        );
+        assert!(matches!(
+            profile.check_metadata("gpt2", Some(2), None, None),
+            Err(ProfileError::MetadataMismatch {
+                field: "tp_size",
+                ..
+            })
+        ));
+        assert!(matches!(
+            profile.check_metadata("gpt2", None, None, Some(Precision::Fp16)),
+            Err(ProfileError::MetadataMismatch {
+                field: "precision",
+                ..
+            })
+        ));
+        assert!(profile.check_metadata("llama", None, None, None).is_err());
+    }
+
+    #[test]
+    fn test_invalid_profile_metadata() {
+        let mut profile = profile();
+        profile.tp_size = 0;
+        assert!(matches!(
+            profile.validate(),
+            Err(ProfileError::InvalidMetadata {
+                field: "tp_size",
+                ..
+            })
+        ));
+    }
+}
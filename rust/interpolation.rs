@@ -0,0 +1,293 @@
+use crate::execution_result::{LayerExecutionResult, ProfileResult};
+use crate::validation::ProfileError;
+
+/// `intercept + slope * microbatch_size`, fitted by least squares.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AffineFit {
+    pub intercept: f64,
+    pub slope: f64,
+}
+
+impl AffineFit {
+    fn fit(xs: &[f64], ys: &[f64]) -> Self {
+        let n = xs.len() as f64;
+        let mean_x = xs.iter().sum::<f64>() / n;
+        let mean_y = ys.iter().sum::<f64>() / n;
+        let covariance: f64 = xs
+            .iter()
+            .zip(ys.iter())
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let variance: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+        let slope = covariance / variance;
+        AffineFit {
+            intercept: mean_y - slope * mean_x,
+            slope,
+        }
+    }
+
+    /// Value at the given microbatch size. Never negative, even when extrapolating.
+    pub fn at(&self, microbatch_size: u32) -> f64 {
+        (self.intercept + self.slope * microbatch_size as f64).max(0.0)
+    }
+}
+
+/// Latency and memory of a layer as functions of the microbatch size.
+#[derive(Clone, Debug)]
+pub struct LayerModel {
+    pub layer_index: u32,
+    pub layer_name: String,
+    pub forward: AffineFit,
+    pub backward: AffineFit,
+    pub recompute: AffineFit,
+    pub forward_std: AffineFit,
+    pub backward_std: AffineFit,
+    pub mem_required: AffineFit,
+    pub activation_size: AffineFit,
+    pub activation_mem: AffineFit,
+    pub checkpoint_mem_saved: AffineFit,
+    // Independent of the microbatch size; taken from the largest microbatch profiled
+    pub param_mem: u64,
+    pub num_parameters: u64,
+    // Largest relative residual of forward + backward latency and of mem_required
+    pub latency_error: f64,
+    pub memory_error: f64,
+}
+
+/// Per-layer models fitted over profiles of the same model with different microbatch sizes.
+pub struct ProfileModel {
+    pub model_name: String,
+    pub tp_size: u32,
+    pub precision: String,
+    pub microbatch_sizes: Vec<u32>,
+    pub layers: Vec<LayerModel>,
+}
+
+fn relative_error(predicted: f64, observed: f64) -> f64 {
+    if observed > 0.0 {
+        (predicted - observed).abs() / observed
+    } else {
+        predicted.abs()
+    }
+}
+
+impl ProfileModel {
+    /// Fits affine models in the microbatch size. With exactly two microbatch sizes
+    /// the fit is exact and the fit error is zero.
+    pub fn fit(profiles: &[ProfileResult]) -> Result<Self, ProfileError> {
+        if profiles.is_empty() {
+            return Err(ProfileError::NoProfiles);
+        }
+        let mut profiles: Vec<&ProfileResult> = profiles.iter().collect();
+        profiles.sort_by_key(|profile| profile.microbatch_size);
+        for profile in profiles.iter() {
+            profile.validate()?;
+            profiles[0].check_same_layers(profile)?;
+        }
+        let mut microbatch_sizes: Vec<u32> = profiles
+            .iter()
+            .map(|profile| profile.microbatch_size)
+            .collect();
+        microbatch_sizes.dedup();
+        if microbatch_sizes.len() < 2 {
+            return Err(ProfileError::InvalidMetadata {
+                field: "microbatch_size",
+                reason: "profiles of at least two microbatch sizes are needed".to_string(),
+            });
+        }
+
+        let xs: Vec<f64> = profiles
+            .iter()
+            .map(|profile| profile.microbatch_size as f64)
+            .collect();
+        let largest = profiles[profiles.len() - 1];
+        let layers = (0..largest.layers.len())
+            .map(|l| {
+                let runs: Vec<&LayerExecutionResult> =
+                    profiles.iter().map(|profile| &profile.layers[l]).collect();
+                let fit = |field: fn(&LayerExecutionResult) -> f64| {
+                    let ys: Vec<f64> = runs.iter().map(|&layer| field(layer)).collect();
+                    AffineFit::fit(&xs, &ys)
+                };
+
+                let forward = fit(|layer| layer.forward);
+                let backward = fit(|layer| layer.backward);
+                let mem_required = fit(|layer| layer.mem_required as f64);
+                let max_error = |predict: &dyn Fn(u32) -> f64, observe: &dyn Fn(usize) -> f64| {
+                    profiles
+                        .iter()
+                        .enumerate()
+                        .map(|(r, profile)| {
+                            relative_error(predict(profile.microbatch_size), observe(r))
+                        })
+                        .fold(0.0, f64::max)
+                };
+
+                LayerModel {
+                    layer_index: largest.layers[l].layer_index,
+                    layer_name: largest.layers[l].layer_name.clone(),
+                    forward,
+                    backward,
+                    recompute: fit(|layer| layer.recompute),
+                    forward_std: fit(|layer| layer.forward_std),
+                    backward_std: fit(|layer| layer.backward_std),
+                    mem_required,
+                    activation_size: fit(|layer| layer.activation_size as f64),
+                    activation_mem: fit(|layer| layer.activation_mem as f64),
+                    checkpoint_mem_saved: fit(|layer| layer.checkpoint_mem_saved as f64),
+                    param_mem: largest.layers[l].param_mem,
+                    num_parameters: largest.layers[l].num_parameters,
+                    latency_error: max_error(&|mb| forward.at(mb) + backward.at(mb), &|r| {
+                        runs[r].forward + runs[r].backward
+                    }),
+                    memory_error: max_error(&|mb| mem_required.at(mb), &|r| {
+                        runs[r].mem_required as f64
+                    }),
+                }
+            })
+            .collect();
+
+        Ok(ProfileModel {
+            model_name: largest.model_name.clone(),
+            tp_size: largest.tp_size,
+            precision: largest.precision.clone(),
+            microbatch_sizes,
+            layers,
+        })
+    }
+
+    /// Largest relative residual of any layer latency.
+    pub fn latency_error(&self) -> f64 {
+        self.layers
+            .iter()
+            .map(|layer| layer.latency_error)
+            .fold(0.0, f64::max)
+    }
+
+    /// Largest relative residual of any layer memory.
+    pub fn memory_error(&self) -> f64 {
+        self.layers
+            .iter()
+            .map(|layer| layer.memory_error)
+            .fold(0.0, f64::max)
+    }
+
+    /// Profile predicted for the given microbatch size.
+    pub fn synthesize(&self, microbatch_size: u32) -> Result<ProfileResult, ProfileError> {
+        if microbatch_size == 0 {
+            return Err(ProfileError::InvalidMetadata {
+                field: "microbatch_size",
+                reason: "must be positive".to_string(),
+            });
+        }
+        if !(self.microbatch_sizes[0]..=self.microbatch_sizes[self.microbatch_sizes.len() - 1])
+            .contains(&microbatch_size)
+        {
+            log::warn!(
+                "Extrapolating microbatch size {} from profiled sizes {:?}",
+                microbatch_size,
+                self.microbatch_sizes
+            );
+        }
+        let bytes = |fit: &AffineFit| fit.at(microbatch_size).round() as u64;
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| LayerExecutionResult {
+                layer_index: layer.layer_index,
+                layer_name: layer.layer_name.clone(),
+                forward: layer.forward.at(microbatch_size),
+                backward: layer.backward.at(microbatch_size),
+                mem_required: bytes(&layer.mem_required).max(layer.param_mem),
+                activation_size: bytes(&layer.activation_size),
+                param_mem: layer.param_mem,
+                activation_mem: bytes(&layer.activation_mem),
+                num_parameters: layer.num_parameters,
+                recompute: layer.recompute.at(microbatch_size),
+                checkpoint_mem_saved: bytes(&layer.checkpoint_mem_saved),
+                forward_std: layer.forward_std.at(microbatch_size),
+                backward_std: layer.backward_std.at(microbatch_size),
+                samples: vec![],
+            })
+            .collect();
+        Ok(ProfileResult::new(
+            self.model_name.clone(),
+            microbatch_size,
+            self.tp_size,
+            self.precision.clone(),
+            layers,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Latency 0.5 + mb ms and memory 100 + 10 * mb bytes per layer
+    fn profile(microbatch_size: u32, noise: f64) -> ProfileResult {
+        let layers = (0..3)
+            .map(|i| LayerExecutionResult {
+                layer_index: i,
+                layer_name: format!("layer{}", i),
+                forward: 0.25 + microbatch_size as f64 / 2.0 + noise,
+                backward: 0.25 + microbatch_size as f64 / 2.0,
+                mem_required: 100 + 10 * microbatch_size as u64,
+                param_mem: 100,
+                activation_mem: 10 * microbatch_size as u64,
+                ..Default::default()
+            })
+            .collect();
+        ProfileResult::new(
+            "gpt2".to_string(),
+            microbatch_size,
+            1,
+            "fp32".to_string(),
+            layers,
+        )
+    }
+
+    #[test]
+    fn test_interpolate_affine_profile() {
+        let model = ProfileModel::fit(&[profile(4, 0.0), profile(1, 0.0)]).unwrap();
+        assert_eq!(model.microbatch_sizes, vec![1, 4]);
+        assert!(model.latency_error() < 1e-9);
+        assert!(model.memory_error() < 1e-9);
+
+        let synthesized = model.synthesize(2).unwrap();
+        assert_eq!(synthesized.microbatch_size, 2);
+        let layer = &synthesized.layers[1];
+        assert!((layer.forward + layer.backward - 2.5).abs() < 1e-9);
+        assert_eq!(layer.mem_required, 120);
+        assert_eq!(layer.activation_mem, 20);
+        assert_eq!(layer.param_mem, 100);
+
+        // Extrapolation
+        let layer = &model.synthesize(8).unwrap().layers[0];
+        assert_eq!(layer.mem_required, 180);
+    }
+
+    #[test]
+    fn test_report_fit_error() {
+        let profiles = [profile(1, 0.0), profile(2, 0.3), profile(4, 0.0)];
+        let model = ProfileModel::fit(&profiles).unwrap();
+        assert!(model.latency_error() > 0.01);
+        assert!(model.memory_error() < 1e-9);
+    }
+
+    #[test]
+    fn test_return_error_for_single_microbatch_size() {
+        assert!(matches!(
+            ProfileModel::fit(&[profile(2, 0.0), profile(2, 0.1)]),
+            Err(ProfileError::InvalidMetadata {
+                field: "microbatch_size",
+                ..
+            })
+        ));
+        assert!(ProfileModel::fit(&[]).is_err());
+
+        let mut other = profile(1, 0.0);
+        other.model_name = "llama".to_string();
+        assert!(ProfileModel::fit(&[profile(2, 0.0), other]).is_err());
+    }
+}
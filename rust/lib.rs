@@ -1,48 +1,695 @@
+use crate::aggregation::Estimator;
+use crate::chrome_trace::load_chrome_trace;
+use crate::config::{
+    Cancellation, DeviceClass, LatencyStatistic, LinkSpec, MemoryBudget, MicrobatchRange,
+    PlannerConfig, SearchMode,
+};
+use crate::error::PlannerError;
+use crate::execution_result::{LayerExecutionResult, PipelineExecutionResult, ProfileResult};
+use crate::interpolation::ProfileModel;
+use crate::memory_model::{MemoryModel, Optimizer, Precision};
+use crate::microbatch_planner::MicrobatchPlanner;
 use crate::pipeline_template_generator::PipelineTemplateGenerator;
+use crate::schedule::PipelineSchedule;
+use crate::synthesis::{synthesize_profile, DeviceSpec, TransformerConfig};
+use crate::tensor_parallel::TensorParallelPlanner;
+use crate::validation::{validate_device_class, validate_num_nodes, validate_profile};
+mod aggregation;
+mod chrome_trace;
+mod config;
+mod error;
 mod execution_result;
+mod interpolation;
+mod memory_model;
+mod microbatch_planner;
 mod pipeline_template_generator;
+mod schedule;
+mod synthesis;
+mod tensor_parallel;
+mod validation;
 use env_logger;
 use pyo3::prelude::*;
-use pyo3::types::PyDict;
-use std::fmt;
+use pyo3::types::{PyDict, PyList};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-#[derive(Debug)]
-struct PlannerError {
-    message: String,
+// Keyword arguments accepted by every entry point
+const PLANNER_OPTIONS: [&str; 18] = [
+    "link_bandwidth",
+    "link_latency",
+    "gpus_per_node",
+    "intra_node_bandwidth",
+    "intra_node_latency",
+    "num_microbatches",
+    "max_num_microbatches",
+    "schedule",
+    "num_model_chunks",
+    "latency_statistic",
+    "device_memory",
+    "memory_headroom",
+    "precision",
+    "optimizer",
+    "pareto_frontier",
+    "top_k",
+    "cancellation_token",
+    "timeout",
+];
+
+/// Extracts a keyword argument. Arguments given as None are treated as absent.
+fn get_option<'py, T: FromPyObject<'py>>(
+    options: Option<&Bound<'py, PyDict>>,
+    name: &str,
+) -> PyResult<Option<T>> {
+    match options {
+        Some(options) => match options.get_item(name)? {
+            Some(value) if !value.is_none() => Ok(Some(value.extract()?)),
+            _ => Ok(None),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Cancels planning from another Python thread, e.g. when a reconfiguration makes the
+/// pipelines being planned obsolete. Planning then raises `CancelledError`.
+#[pyclass]
+#[derive(Clone, Default)]
+struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
 }
 
-impl PlannerError {
-    fn new(message: &str) -> Self {
-        PlannerError {
-            message: message.to_string(),
+#[pymethods]
+impl CancellationToken {
+    #[new]
+    fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Cancellation of planning by the token and timeout (in seconds) given, if any.
+/// The timeout starts now.
+fn cancellation(token: Option<CancellationToken>, timeout: Option<f64>) -> PyResult<Cancellation> {
+    let timeout = match timeout {
+        Some(timeout) if !(timeout.is_finite() && timeout >= 0.0) => {
+            return Err(PlannerError::invalid_config(format!(
+                "timeout must be a non-negative number of seconds, got {}",
+                timeout
+            ))
+            .into())
+        }
+        timeout => timeout.map(Duration::from_secs_f64),
+    };
+    Ok(Cancellation::new(
+        token.unwrap_or_default().cancelled,
+        timeout,
+    ))
+}
+
+/// Builds the planner configuration from the keyword arguments of an entry point.
+fn planner_config(options: Option<&Bound<'_, PyDict>>) -> PyResult<PlannerConfig> {
+    if let Some(options) = options {
+        for (key, _) in options.iter() {
+            let key: String = key.extract()?;
+            if !PLANNER_OPTIONS.contains(&key.as_str()) {
+                return Err(pyo3::exceptions::PyTypeError::new_err(format!(
+                    "Unexpected planner option: {}",
+                    key
+                )));
+            }
         }
     }
+
+    let link_bandwidth: Option<f64> = get_option(options, "link_bandwidth")?;
+    let link_latency: f64 = get_option(options, "link_latency")?.unwrap_or(0.0);
+    let gpus_per_node: u32 = get_option(options, "gpus_per_node")?.unwrap_or(1);
+    let intra_node_bandwidth: Option<f64> = get_option(options, "intra_node_bandwidth")?;
+    let intra_node_latency: f64 = get_option(options, "intra_node_latency")?.unwrap_or(0.0);
+    let num_microbatches: Option<u32> = get_option(options, "num_microbatches")?;
+    let max_num_microbatches: Option<u32> = get_option(options, "max_num_microbatches")?;
+    let schedule: String = get_option(options, "schedule")?.unwrap_or("1f1b".to_string());
+    let num_model_chunks: u32 = get_option(options, "num_model_chunks")?.unwrap_or(1);
+    let latency_statistic: String =
+        get_option(options, "latency_statistic")?.unwrap_or("mean".to_string());
+    let device_memory: Option<u64> = get_option(options, "device_memory")?;
+    let memory_headroom: f64 = get_option(options, "memory_headroom")?.unwrap_or(0.0);
+    MemoryBudget::validate_headroom(memory_headroom)?;
+    let precision: Option<String> = get_option(options, "precision")?;
+    let optimizer: Option<String> = get_option(options, "optimizer")?;
+    let pareto_frontier: bool = get_option(options, "pareto_frontier")?.unwrap_or(false);
+    let top_k: Option<usize> = get_option(options, "top_k")?;
+    let cancellation_token: Option<CancellationToken> = get_option(options, "cancellation_token")?;
+    let timeout: Option<f64> = get_option(options, "timeout")?;
+
+    Ok(PlannerConfig {
+        link: match link_bandwidth {
+            Some(bandwidth) => LinkSpec::new(bandwidth, link_latency),
+            None => LinkSpec::default(),
+        },
+        intra_node_link: match intra_node_bandwidth {
+            Some(bandwidth) => LinkSpec::new(bandwidth, intra_node_latency),
+            None => LinkSpec::default(),
+        },
+        gpus_per_node,
+        num_microbatches: match (num_microbatches, max_num_microbatches) {
+            (Some(min), Some(max)) => MicrobatchRange::new(min, max)?,
+            (Some(min), None) => MicrobatchRange::exact(min)?,
+            (None, Some(_)) => {
+                return Err(PlannerError::invalid_config(
+                    "max_num_microbatches requires num_microbatches",
+                )
+                .into())
+            }
+            (None, None) => MicrobatchRange::default(),
+        },
+        schedule: schedule::get_schedule(schedule.as_str(), num_model_chunks)?,
+        latency_statistic: LatencyStatistic::from_name(latency_statistic.as_str())?,
+        memory_budget: device_memory
+            .map(|capacity| MemoryBudget::new(capacity, memory_headroom))
+            .transpose()?,
+        memory_model: match (precision, optimizer) {
+            (Some(precision), Some(optimizer)) => Some(MemoryModel::new(
+                Precision::from_name(precision.as_str())?,
+                Optimizer::from_name(optimizer.as_str())?,
+            )),
+            (None, None) => None,
+            _ => {
+                return Err(PlannerError::invalid_config(
+                    "precision and optimizer must be given together",
+                )
+                .into())
+            }
+        },
+        search: match (pareto_frontier, top_k) {
+            (true, None) => SearchMode::ParetoFrontier,
+            (false, None) => SearchMode::Best,
+            (false, Some(0)) => {
+                return Err(PlannerError::invalid_config("top_k must be positive").into())
+            }
+            (false, Some(k)) => SearchMode::TopK(k),
+            (true, Some(_)) => {
+                return Err(PlannerError::invalid_config(
+                    "pareto_frontier and top_k cannot be combined",
+                )
+                .into())
+            }
+        },
+        device_classes: vec![],
+        cancellation: cancellation(cancellation_token, timeout)?,
+    })
 }
 
-impl fmt::Display for PlannerError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "PlannerError: {}", self.message)
+/// Planner configuration of entry points that return a single template per key, where
+/// the options returning several templates per number of nodes cannot be honored.
+fn single_template_config(options: Option<&Bound<'_, PyDict>>) -> PyResult<PlannerConfig> {
+    let config = planner_config(options)?;
+    if config.search != SearchMode::Best {
+        return Err(PlannerError::invalid_config(
+            "pareto_frontier and top_k cannot be given when a single template is returned",
+        )
+        .into());
     }
+    Ok(config)
 }
 
-impl std::error::Error for PlannerError {}
+/// Converts a planned pipeline into a cornstarch `PipelineTemplate`.
+fn to_py_template(
+    py: Python<'_>,
+    class: &PyObject,
+    model_name: &str,
+    generator: &PipelineTemplateGenerator,
+    result: &PipelineExecutionResult,
+    schedule: &dyn PipelineSchedule,
+) -> PyResult<PyObject> {
+    // PipelineTemplate extrapolates its latency from 4 * num_stages microbatches,
+    // with the slowest stage latency as the slope.
+    let num_stages = result.stages.len() as u32;
+    let py_template = class
+        .call1(
+            py,
+            (
+                model_name,
+                result.get_modules_per_stage(&generator.layer_execution_results),
+                result.latency_with_mb(4 * num_stages, schedule),
+                result.stage_latency(result.kstar),
+                result.mem_required(),
+            ),
+        )?
+        .to_object(py);
+    py_template.setattr(
+        py,
+        "activation_checkpointing",
+        result
+            .stages
+            .iter()
+            .map(|stage| stage.checkpointed)
+            .collect::<Vec<bool>>(),
+    )?;
+    py_template.setattr(py, "stage_memory", result.stage_memory.clone())?;
+    py_template.setattr(
+        py,
+        "stages_per_node",
+        result.stages_per_node(generator.config().gpus_per_node),
+    )?;
+    Ok(py_template)
+}
 
-impl From<PlannerError> for PyErr {
-    fn from(error: PlannerError) -> PyErr {
-        PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(error.to_string())
-    }
+/// Converts the templates planned for each number of nodes into cornstarch
+/// `PipelineTemplate`s, keyed by number of nodes. With a search mode other than the
+/// best template, every number of nodes has a list of templates from the fastest.
+fn to_py_templates(
+    model_name: &str,
+    generator: &PipelineTemplateGenerator,
+    num_nodes: &[u32],
+) -> PyResult<Py<PyDict>> {
+    let schedule = generator.config().schedule.clone();
+    let multiple_templates = generator.config().search != SearchMode::Best;
+
+    Python::with_gil(|py| {
+        let results = PyDict::new_bound(py);
+
+        let module = PyModule::import_bound(py, "cornstarch.pipeline_template")?;
+        let class = module.getattr("PipelineTemplate")?.into_py(py);
+
+        for &num_node in num_nodes {
+            let templates = generator.get_pipeline_templates(num_node)?;
+            let mut py_templates = templates
+                .iter()
+                .map(|result| {
+                    to_py_template(py, &class, model_name, generator, result, schedule.as_ref())
+                })
+                .collect::<PyResult<Vec<PyObject>>>()?;
+
+            if multiple_templates {
+                results.set_item(num_node, py_templates)?;
+            } else {
+                results.set_item(num_node, py_templates.swap_remove(0))?;
+            }
+        }
+
+        Ok(results.into())
+    })
 }
 
 #[pyfunction]
+#[pyo3(signature = (model_name, profile_data, num_nodes, **options))]
 fn create_pipeline_templates(
+    py: Python<'_>,
     model_name: String,
-    profile_data: Vec<execution_result::LayerExecutionResult>,
+    profile_data: Vec<LayerExecutionResult>,
     mut num_nodes: Vec<u32>,
+    options: Option<&Bound<'_, PyDict>>,
 ) -> PyResult<Py<PyDict>> {
+    validate_profile(&profile_data)?;
+    validate_num_nodes(&num_nodes)?;
     num_nodes.sort();
 
-    let mut generator = PipelineTemplateGenerator::new(profile_data);
-    generator.divide_and_conquer(num_nodes[num_nodes.len() - 1])?;
+    let mut generator = PipelineTemplateGenerator::new(profile_data, planner_config(options)?);
+    py.allow_threads(|| generator.divide_and_conquer(num_nodes[num_nodes.len() - 1]))?;
+    to_py_templates(model_name.as_str(), &generator, &num_nodes)
+}
+
+/// Planner that keeps what it planned between calls. Templates for more nodes than
+/// planned for so far, e.g. after the cluster has grown, only plan the pipelines
+/// with more stages.
+#[pyclass]
+struct PipelineTemplatePlanner {
+    model_name: String,
+    generator: PipelineTemplateGenerator,
+}
+
+#[pymethods]
+impl PipelineTemplatePlanner {
+    #[new]
+    #[pyo3(signature = (model_name, profile_data, **options))]
+    fn new(
+        model_name: String,
+        profile_data: Vec<LayerExecutionResult>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        validate_profile(&profile_data)?;
+        // Cancellation is per call rather than for the lifetime of the planner
+        for key in ["cancellation_token", "timeout"] {
+            if get_option::<PyObject>(options, key)?.is_some() {
+                return Err(PlannerError::invalid_config(format!(
+                    "{} is given to create_pipeline_templates of the planner",
+                    key
+                ))
+                .into());
+            }
+        }
+        Ok(PipelineTemplatePlanner {
+            model_name,
+            generator: PipelineTemplateGenerator::new(profile_data, planner_config(options)?),
+        })
+    }
+
+    /// Same as `create_pipeline_templates` with the profile and options of the planner.
+    /// Pipelines planned before a cancellation are kept for later calls.
+    #[pyo3(signature = (num_nodes, *, cancellation_token=None, timeout=None))]
+    fn create_pipeline_templates(
+        &mut self,
+        py: Python<'_>,
+        mut num_nodes: Vec<u32>,
+        cancellation_token: Option<CancellationToken>,
+        timeout: Option<f64>,
+    ) -> PyResult<Py<PyDict>> {
+        validate_num_nodes(&num_nodes)?;
+        num_nodes.sort();
+
+        self.generator
+            .set_cancellation(cancellation(cancellation_token, timeout)?);
+        let generator = &mut self.generator;
+        py.allow_threads(|| generator.divide_and_conquer(num_nodes[num_nodes.len() - 1]))?;
+        to_py_templates(self.model_name.as_str(), &self.generator, &num_nodes)
+    }
+}
+
+/// Plans pipelines from a profile file written by the profiler. Fails if the profile
+/// was taken for another model, or another tp_size, microbatch_size or precision if given.
+/// Memory is modeled with the precision of the profile when an optimizer is given.
+#[pyfunction]
+#[pyo3(signature = (model_name, profile_path, num_nodes, *, tp_size=None, microbatch_size=None, precision=None, **options))]
+#[allow(clippy::too_many_arguments)]
+fn create_pipeline_templates_from_profile(
+    py: Python<'_>,
+    model_name: String,
+    profile_path: PathBuf,
+    num_nodes: Vec<u32>,
+    tp_size: Option<u32>,
+    microbatch_size: Option<u32>,
+    precision: Option<String>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Py<PyDict>> {
+    let profile = ProfileResult::load(&profile_path)?;
+    let precision = match precision {
+        Some(precision) => Some(Precision::from_name(precision.as_str())?),
+        None => None,
+    };
+    profile.check_metadata(model_name.as_str(), tp_size, microbatch_size, precision)?;
+    if let Some(options) = options {
+        if get_option::<String>(Some(options), "optimizer")?.is_some() {
+            options.set_item("precision", profile.precision.as_str())?;
+        }
+    }
+    create_pipeline_templates(py, model_name, profile.layers, num_nodes, options)
+}
+
+/// Writes a profile file that `create_pipeline_templates_from_profile` can read.
+#[pyfunction]
+#[pyo3(signature = (profile_path, model_name, microbatch_size, tp_size, precision, profile_data))]
+fn save_profile(
+    profile_path: PathBuf,
+    model_name: String,
+    microbatch_size: u32,
+    tp_size: u32,
+    precision: String,
+    profile_data: Vec<LayerExecutionResult>,
+) -> PyResult<()> {
+    ProfileResult::new(
+        model_name,
+        microbatch_size,
+        tp_size,
+        precision,
+        profile_data,
+    )
+    .save(&profile_path)?;
+    Ok(())
+}
+
+/// Merges profile files of several runs or nodes, leaving out outlier runs,
+/// and writes the consolidated profile to `output_path` if given.
+/// Returns the indices of outlier runs and latency statistics of each layer.
+#[pyfunction]
+#[pyo3(signature = (profile_paths, output_path=None, *, estimator="trimmed_mean", trim_fraction=0.1))]
+fn aggregate_profiles(
+    py: Python<'_>,
+    profile_paths: Vec<PathBuf>,
+    output_path: Option<PathBuf>,
+    estimator: &str,
+    trim_fraction: f64,
+) -> PyResult<Py<PyDict>> {
+    let estimator = Estimator::from_name(estimator, trim_fraction)?;
+    let profiles = profile_paths
+        .iter()
+        .map(|path| ProfileResult::load(path))
+        .collect::<Result<Vec<ProfileResult>, _>>()?;
+    let aggregated = aggregation::aggregate_profiles(&profiles, estimator)?;
+    if let Some(output_path) = output_path {
+        aggregated.profile.save(&output_path)?;
+    }
+
+    let report = PyDict::new_bound(py);
+    report.set_item("outlier_runs", aggregated.outlier_runs)?;
+    let layers = PyList::empty_bound(py);
+    for statistics in aggregated.layers {
+        let layer = PyDict::new_bound(py);
+        layer.set_item("layer_index", statistics.layer_index)?;
+        layer.set_item("layer_name", statistics.layer_name)?;
+        layer.set_item("mean", statistics.mean)?;
+        layer.set_item("median", statistics.median)?;
+        layer.set_item("trimmed_mean", statistics.trimmed_mean)?;
+        layer.set_item("std", statistics.std)?;
+        layer.set_item("confidence_interval", statistics.confidence_interval)?;
+        layers.append(layer)?;
+    }
+    report.set_item("layers", layers)?;
+    Ok(report.into())
+}
+
+/// Predicts the profile of an unprofiled microbatch size from profile files of
+/// at least two other sizes, and writes it to `output_path`.
+/// Returns the largest relative residuals of the fit.
+#[pyfunction]
+#[pyo3(signature = (profile_paths, microbatch_size, output_path))]
+fn interpolate_profile(
+    py: Python<'_>,
+    profile_paths: Vec<PathBuf>,
+    microbatch_size: u32,
+    output_path: PathBuf,
+) -> PyResult<Py<PyDict>> {
+    let profiles = profile_paths
+        .iter()
+        .map(|path| ProfileResult::load(path))
+        .collect::<Result<Vec<ProfileResult>, _>>()?;
+    let model = ProfileModel::fit(&profiles)?;
+    model.synthesize(microbatch_size)?.save(&output_path)?;
+
+    let report = PyDict::new_bound(py);
+    report.set_item("latency_error", model.latency_error())?;
+    report.set_item("memory_error", model.memory_error())?;
+    Ok(report.into())
+}
+
+/// Predicts the profile of a transformer from its HuggingFace config and the roofline
+/// of a device, and writes it to `output_path` for planning without GPUs.
+#[pyfunction]
+#[pyo3(signature = (config_path, output_path, *, peak_flops, memory_bandwidth, microbatch_size=1, seq_length=None, tp_size=1, precision="bf16", optimizer="adamw"))]
+#[allow(clippy::too_many_arguments)]
+fn synthesize_profile_from_config(
+    config_path: PathBuf,
+    output_path: PathBuf,
+    peak_flops: f64,
+    memory_bandwidth: f64,
+    microbatch_size: u32,
+    seq_length: Option<u64>,
+    tp_size: u32,
+    precision: &str,
+    optimizer: &str,
+) -> PyResult<String> {
+    let config = TransformerConfig::load(&config_path, seq_length)?;
+    let device = DeviceSpec {
+        peak_flops,
+        memory_bandwidth,
+    };
+    let memory_model = MemoryModel::new(
+        Precision::from_name(precision)?,
+        Optimizer::from_name(optimizer)?,
+    );
+    synthesize_profile(&config, &device, microbatch_size, tp_size, &memory_model)?
+        .save(&output_path)?;
+    Ok(config.model_name)
+}
+
+/// Imports layer timings from a `torch.profiler` Chrome trace as
+/// `oobleck.planning.profiler.LayerExecutionResult`s, without memory usage.
+#[pyfunction]
+#[pyo3(signature = (trace_path, layer_names))]
+fn import_chrome_trace(
+    py: Python<'_>,
+    trace_path: PathBuf,
+    layer_names: Vec<String>,
+) -> PyResult<Vec<PyObject>> {
+    let layers = load_chrome_trace(&trace_path, &layer_names)?;
+
+    let module = PyModule::import_bound(py, "oobleck.planning.profiler")?;
+    let class = module.getattr("LayerExecutionResult")?.into_py(py);
+    layers
+        .into_iter()
+        .map(|layer| {
+            let kwargs = PyDict::new_bound(py);
+            kwargs.set_item("layer_index", layer.layer_index)?;
+            kwargs.set_item("layer_name", layer.layer_name)?;
+            kwargs.set_item("forward", layer.forward)?;
+            kwargs.set_item("backward", layer.backward)?;
+            kwargs.set_item("mem_required", layer.mem_required)?;
+            kwargs.set_item("samples", layer.samples)?;
+            class.call_bound(py, (), Some(&kwargs))
+        })
+        .collect()
+}
+
+/// Plans a pipeline over `num_nodes[c]` nodes of `device_classes[c]`.
+#[pyfunction]
+#[pyo3(signature = (model_name, profile_data, device_classes, num_nodes, **options))]
+fn create_heterogeneous_pipeline_template(
+    py: Python<'_>,
+    model_name: String,
+    profile_data: Vec<LayerExecutionResult>,
+    mut device_classes: Vec<DeviceClass>,
+    num_nodes: Vec<u32>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<PyObject> {
+    validate_profile(&profile_data)?;
+    if num_nodes.iter().sum::<u32>() == 0 {
+        return Err(PlannerError::invalid_config("At least one node is required").into());
+    }
+    for class in device_classes.iter() {
+        validate_device_class(class)?;
+    }
+    let mut config = single_template_config(options)?;
+    let memory_headroom: f64 = get_option(options, "memory_headroom")?.unwrap_or(0.0);
+    for class in device_classes.iter_mut() {
+        class.memory_budget = class
+            .memory_budget
+            .map(|budget| MemoryBudget::new(budget.capacity, memory_headroom))
+            .transpose()?;
+    }
+    config.device_classes = device_classes;
+
+    let schedule = config.schedule.clone();
+    let mut generator = PipelineTemplateGenerator::new(profile_data, config);
+    py.allow_threads(|| generator.divide_and_conquer_heterogeneous(&num_nodes))?;
+    let result = generator.get_heterogeneous_pipeline_template(&num_nodes)?;
+
+    Python::with_gil(|py| {
+        let module = PyModule::import_bound(py, "cornstarch.pipeline_template")?;
+        let class = module.getattr("PipelineTemplate")?.into_py(py);
+
+        let py_template = to_py_template(
+            py,
+            &class,
+            model_name.as_str(),
+            &generator,
+            &result,
+            schedule.as_ref(),
+        )?;
+        py_template.setattr(
+            py,
+            "device_classes",
+            result
+                .stages
+                .iter()
+                .map(|stage| generator.device_classes()[stage.device_class].name.clone())
+                .collect::<Vec<String>>(),
+        )?;
+        Ok(py_template)
+    })
+}
+
+/// Plans pipelines for every tensor parallel degree `profile_data` has a profile for,
+/// and returns the fastest TP x PP combination for each number of GPUs.
+#[pyfunction]
+#[pyo3(signature = (model_name, profile_data, num_gpus, **options))]
+fn create_tensor_parallel_pipeline_templates(
+    py: Python<'_>,
+    model_name: String,
+    profile_data: HashMap<u32, Vec<LayerExecutionResult>>,
+    mut num_gpus: Vec<u32>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Py<PyDict>> {
+    for profile in profile_data.values() {
+        validate_profile(profile)?;
+    }
+    validate_num_nodes(&num_gpus)?;
+    num_gpus.sort();
+
+    let config = single_template_config(options)?;
+    let schedule = config.schedule.clone();
+    let mut planner = TensorParallelPlanner::new(profile_data.into_iter().collect(), config)?;
+    py.allow_threads(|| planner.divide_and_conquer(num_gpus[num_gpus.len() - 1]))?;
+
+    Python::with_gil(|py| {
+        let results = PyDict::new_bound(py);
+
+        let module = PyModule::import_bound(py, "cornstarch.pipeline_template")?;
+        let class = module.getattr("PipelineTemplate")?.into_py(py);
+
+        for num_gpu in num_gpus {
+            let (tp_size, result) = planner.get_pipeline_template(num_gpu)?;
+            let py_template = to_py_template(
+                py,
+                &class,
+                model_name.as_str(),
+                planner.generator(tp_size).unwrap(),
+                &result,
+                schedule.as_ref(),
+            )?;
+            results.set_item(num_gpu, (tp_size, py_template))?;
+        }
+
+        Ok(results.into())
+    })
+}
+
+/// Plans pipelines for every microbatch size `profile_data` has a profile for, each
+/// running `global_batch_size` samples per iteration, and returns the microbatch size
+/// with the highest throughput that fits in memory for each number of nodes.
+#[pyfunction]
+#[pyo3(signature = (model_name, profile_data, num_nodes, global_batch_size, **options))]
+fn create_microbatch_pipeline_templates(
+    py: Python<'_>,
+    model_name: String,
+    profile_data: HashMap<u32, Vec<LayerExecutionResult>>,
+    mut num_nodes: Vec<u32>,
+    global_batch_size: u32,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Py<PyDict>> {
+    for profile in profile_data.values() {
+        validate_profile(profile)?;
+    }
+    validate_num_nodes(&num_nodes)?;
+    num_nodes.sort();
+
+    // The number of microbatches follows from the global batch and the microbatch size
+    for key in ["num_microbatches", "max_num_microbatches"] {
+        if get_option::<u32>(options, key)?.is_some() {
+            return Err(PlannerError::invalid_config(format!(
+                "{} cannot be given with a global batch size",
+                key
+            ))
+            .into());
+        }
+    }
+
+    let config = single_template_config(options)?;
+    let schedule = config.schedule.clone();
+    let mut planner = MicrobatchPlanner::new(
+        profile_data.into_iter().collect(),
+        global_batch_size,
+        config,
+    )?;
+    py.allow_threads(|| planner.divide_and_conquer(num_nodes[num_nodes.len() - 1]))?;
 
     Python::with_gil(|py| {
         let results = PyDict::new_bound(py);
@@ -51,20 +698,16 @@ fn create_pipeline_templates(
         let class = module.getattr("PipelineTemplate")?.into_py(py);
 
         for num_node in num_nodes {
-            let result = generator.get_pipeline_template(num_node).unwrap();
-            let py_template = class
-                .call1(
-                    py,
-                    (
-                        model_name.as_str(),
-                        result.get_modules_per_stage(&generator.layer_execution_results),
-                        result.latency(),
-                        result.stages[result.kstar].latency(),
-                        result.mem_required(),
-                    ),
-                )?
-                .to_object(py);
-            results.set_item(result.stages.len(), py_template)?;
+            let (microbatch_size, result) = planner.get_pipeline_template(num_node)?;
+            let py_template = to_py_template(
+                py,
+                &class,
+                model_name.as_str(),
+                planner.generator(microbatch_size).unwrap(),
+                &result,
+                schedule.as_ref(),
+            )?;
+            results.set_item(num_node, (microbatch_size, py_template))?;
         }
 
         Ok(results.into())
@@ -72,16 +715,30 @@ fn create_pipeline_templates(
 }
 
 #[pymodule]
-fn planner(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+fn planner(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
     let _ = env_logger::try_init();
+    error::add_exceptions(py, m)?;
+    m.add_class::<CancellationToken>()?;
+    m.add_class::<PipelineTemplatePlanner>()?;
     m.add_function(wrap_pyfunction!(create_pipeline_templates, m)?)?;
+    m.add_function(wrap_pyfunction!(create_pipeline_templates_from_profile, m)?)?;
+    m.add_function(wrap_pyfunction!(save_profile, m)?)?;
+    m.add_function(wrap_pyfunction!(import_chrome_trace, m)?)?;
+    m.add_function(wrap_pyfunction!(aggregate_profiles, m)?)?;
+    m.add_function(wrap_pyfunction!(interpolate_profile, m)?)?;
+    m.add_function(wrap_pyfunction!(synthesize_profile_from_config, m)?)?;
+    m.add_function(wrap_pyfunction!(create_heterogeneous_pipeline_template, m)?)?;
+    m.add_function(wrap_pyfunction!(
+        create_tensor_parallel_pipeline_templates,
+        m
+    )?)?;
+    m.add_function(wrap_pyfunction!(create_microbatch_pipeline_templates, m)?)?;
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::execution_result::LayerExecutionResult;
 
     fn prepare(
         num_layers: u32,
@@ -108,6 +765,7 @@ mod test {
                 } else {
                     (i + 1) as u64
                 },
+                ..Default::default()
             });
         }
 
@@ -124,7 +782,9 @@ mod test {
 
         let model_name = "gpt2".to_string();
 
-        create_pipeline_templates(model_name, layer_results, num_nodes).unwrap();
+        Python::with_gil(|py| {
+            create_pipeline_templates(py, model_name, layer_results, num_nodes, None).unwrap();
+        });
 
         // let py = Python::acquire_gil();
         // let py_result = result.extract::<PyList>(py).unwrap();
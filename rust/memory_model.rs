@@ -0,0 +1,196 @@
+use crate::execution_result::LayerExecutionResult;
+use crate::PlannerError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precision {
+    Fp32,
+    // Mixed precision with fp32 master weights
+    Fp16,
+    Bf16,
+    // Transformer Engine style fp8: fp8 weights, bf16 gradients, fp32 master weights
+    Fp8,
+}
+
+impl Precision {
+    pub fn from_name(name: &str) -> Result<Self, PlannerError> {
+        match name.to_lowercase().as_str() {
+            "fp32" | "float32" => Ok(Precision::Fp32),
+            "fp16" | "float16" => Ok(Precision::Fp16),
+            "bf16" | "bfloat16" => Ok(Precision::Bf16),
+            "fp8" => Ok(Precision::Fp8),
+            _ => Err(PlannerError::invalid_config(format!(
+                "Unknown precision: {}",
+                name
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Optimizer {
+    Adam,
+    AdamW,
+    // With a momentum buffer
+    Sgd,
+    // Without first moment (beta1=None), so only the factored second moment is kept
+    Adafactor,
+}
+
+impl Optimizer {
+    /// Accepts either a short name or a class path such as `torch.optim.AdamW`.
+    pub fn from_name(name: &str) -> Result<Self, PlannerError> {
+        let class_name = name.rsplit('.').next().unwrap_or(name);
+        match class_name.to_lowercase().as_str() {
+            "adam" => Ok(Optimizer::Adam),
+            "adamw" => Ok(Optimizer::AdamW),
+            "sgd" => Ok(Optimizer::Sgd),
+            "adafactor" => Ok(Optimizer::Adafactor),
+            _ => Err(PlannerError::invalid_config(format!(
+                "Unknown optimizer: {}",
+                name
+            ))),
+        }
+    }
+}
+
+/// Bytes kept per parameter, independent of the number of microbatches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BytesPerParameter {
+    pub weight: u64,
+    pub gradient: u64,
+    pub master_weight: u64,
+    pub optimizer_states: u64,
+}
+
+impl BytesPerParameter {
+    pub fn total(&self) -> u64 {
+        self.weight + self.gradient + self.master_weight + self.optimizer_states
+    }
+}
+
+/// Computes static memory of layers from their parameter counts,
+/// so that a profile can be reused for another precision or optimizer.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryModel {
+    pub precision: Precision,
+    pub optimizer: Optimizer,
+}
+
+impl MemoryModel {
+    pub fn new(precision: Precision, optimizer: Optimizer) -> Self {
+        MemoryModel {
+            precision,
+            optimizer,
+        }
+    }
+
+    pub fn bytes_per_parameter(&self) -> BytesPerParameter {
+        let (weight, gradient, master_weight) = match self.precision {
+            Precision::Fp32 => (4, 4, 0),
+            Precision::Fp16 | Precision::Bf16 => (2, 2, 4),
+            Precision::Fp8 => (1, 2, 4),
+        };
+        // Optimizer states are always kept in fp32
+        let optimizer_states = match self.optimizer {
+            Optimizer::Adam | Optimizer::AdamW => 8,
+            Optimizer::Sgd => 4,
+            Optimizer::Adafactor => 0,
+        };
+
+        BytesPerParameter {
+            weight,
+            gradient,
+            master_weight,
+            optimizer_states,
+        }
+    }
+
+    /// Replaces the profiled static memory of the layer with the modeled one.
+    /// Layers without a parameter count keep their profiled memory. As with stage costs,
+    /// layers profiled without a breakdown are taken to have no activation memory.
+    pub fn apply(&self, layer: &mut LayerExecutionResult) {
+        if layer.num_parameters == 0 {
+            return;
+        }
+
+        layer.param_mem = layer.num_parameters * self.bytes_per_parameter().total();
+        layer.mem_required = layer.param_mem + layer.activation_mem;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bytes_per_parameter() {
+        let model = MemoryModel::new(Precision::Fp32, Optimizer::Adam);
+        assert_eq!(model.bytes_per_parameter().total(), 16);
+
+        // The well-known 16 bytes per parameter of mixed precision Adam
+        let model = MemoryModel::new(Precision::Bf16, Optimizer::AdamW);
+        assert_eq!(model.bytes_per_parameter().total(), 16);
+        assert_eq!(model.bytes_per_parameter().master_weight, 4);
+
+        let model = MemoryModel::new(Precision::Fp16, Optimizer::Sgd);
+        assert_eq!(model.bytes_per_parameter().total(), 12);
+
+        let model = MemoryModel::new(Precision::Fp8, Optimizer::Adafactor);
+        assert_eq!(model.bytes_per_parameter().total(), 7);
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Precision::from_name("bf16").unwrap(), Precision::Bf16);
+        assert!(Precision::from_name("int4").is_err());
+        assert_eq!(
+            Optimizer::from_name("torch.optim.AdamW").unwrap(),
+            Optimizer::AdamW
+        );
+        assert_eq!(Optimizer::from_name("sgd").unwrap(), Optimizer::Sgd);
+        assert!(Optimizer::from_name("torch.optim.LBFGS").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let model = MemoryModel::new(Precision::Fp32, Optimizer::Sgd);
+        let mut layer = LayerExecutionResult {
+            num_parameters: 100,
+            mem_required: 10,
+            activation_mem: 4,
+            ..Default::default()
+        };
+        model.apply(&mut layer);
+        assert_eq!(layer.param_mem, 1200);
+        assert_eq!(layer.mem_required, 1204);
+
+        let mut layer = LayerExecutionResult {
+            mem_required: 10,
+            ..Default::default()
+        };
+        model.apply(&mut layer);
+        assert_eq!(layer.param_mem, 0);
+        assert_eq!(layer.mem_required, 10);
+
+        // Older profiles only have the total memory, all of it static
+        let mut layer = LayerExecutionResult {
+            num_parameters: 100,
+            mem_required: 500,
+            ..Default::default()
+        };
+        model.apply(&mut layer);
+        assert_eq!(layer.activation_mem, 0);
+        assert_eq!(layer.mem_required, 1200);
+
+        let mut layer = LayerExecutionResult {
+            num_parameters: 100,
+            mem_required: 500,
+            param_mem: 300,
+            activation_mem: 200,
+            ..Default::default()
+        };
+        model.apply(&mut layer);
+        assert_eq!(layer.activation_mem, 200);
+        assert_eq!(layer.mem_required, 1400);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::config::{MicrobatchRange, PlannerConfig};
+use crate::execution_result::{LayerExecutionResult, PipelineExecutionResult};
+use crate::pipeline_template_generator::PipelineTemplateGenerator;
+use crate::PlannerError;
+
+/// Plans pipelines for several microbatch sizes of a fixed global batch and picks
+/// the microbatch size with the highest throughput for each number of nodes.
+///
+/// A pipeline runs the global batch as `global_batch_size / microbatch_size` microbatches,
+/// so larger microbatches run fewer of them, usually faster per sample but with more
+/// activation memory each. Microbatch sizes that do not divide the global batch are skipped.
+pub struct MicrobatchPlanner {
+    global_batch_size: u32,
+    // Key: microbatch size the profile was taken with, sorted
+    generators: Vec<(u32, PipelineTemplateGenerator)>,
+}
+
+impl MicrobatchPlanner {
+    pub fn new(
+        mut profiles: Vec<(u32, Vec<LayerExecutionResult>)>,
+        global_batch_size: u32,
+        config: PlannerConfig,
+    ) -> Result<Self, PlannerError> {
+        if profiles.is_empty() {
+            return Err(PlannerError::invalid_config("No profile is given"));
+        }
+        profiles.sort_by_key(|(microbatch_size, _)| *microbatch_size);
+        for k in 0..profiles.len() {
+            if profiles[k].0 == 0 || (k > 0 && profiles[k].0 == profiles[k - 1].0) {
+                return Err(PlannerError::invalid_config(format!(
+                    "Invalid or duplicate microbatch size: {}",
+                    profiles[k].0
+                )));
+            }
+        }
+        profiles.retain(|(microbatch_size, _)| global_batch_size.is_multiple_of(*microbatch_size));
+        if global_batch_size == 0 || profiles.is_empty() {
+            return Err(PlannerError::invalid_config(format!(
+                "No microbatch size divides the global batch size {}",
+                global_batch_size
+            )));
+        }
+
+        Ok(MicrobatchPlanner {
+            global_batch_size,
+            generators: profiles
+                .into_iter()
+                .map(|(microbatch_size, profile)| {
+                    let config = PlannerConfig {
+                        num_microbatches: MicrobatchRange::exact(
+                            global_batch_size / microbatch_size,
+                        )?,
+                        ..config.clone()
+                    };
+                    Ok((
+                        microbatch_size,
+                        PipelineTemplateGenerator::new(profile, config),
+                    ))
+                })
+                .collect::<Result<_, PlannerError>>()?,
+        })
+    }
+
+    pub fn divide_and_conquer(&mut self, max_num_nodes: u32) -> Result<(), PlannerError> {
+        for (_, generator) in self.generators.iter_mut() {
+            // Pipelines with more stages than layers are infeasible for this
+            // microbatch size rather than an error
+            let stages_per_node = generator.config().gpus_per_node.max(1);
+            let max_num_nodes =
+                max_num_nodes.min(generator.layer_execution_results.len() as u32 / stages_per_node);
+            if max_num_nodes > 0 {
+                generator.divide_and_conquer(max_num_nodes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Generator of the given microbatch size.
+    pub fn generator(&self, microbatch_size: u32) -> Option<&PipelineTemplateGenerator> {
+        self.generators
+            .iter()
+            .find(|(size, _)| *size == microbatch_size)
+            .map(|(_, generator)| generator)
+    }
+
+    /// Samples per millisecond of a pipeline running the whole global batch.
+    pub fn throughput(&self, result: &PipelineExecutionResult) -> f64 {
+        self.global_batch_size as f64 / result.latency()
+    }
+
+    /// Template with the highest throughput over all microbatch sizes that fit in memory,
+    /// along with the chosen microbatch size. If none is feasible, returns the most
+    /// telling error of the microbatch sizes tried.
+    pub fn get_pipeline_template(
+        &self,
+        num_nodes: u32,
+    ) -> Result<(u32, PipelineExecutionResult), PlannerError> {
+        let mut best: Option<(u32, PipelineExecutionResult)> = None;
+        let mut error: Option<PlannerError> = None;
+
+        for (microbatch_size, generator) in self.generators.iter() {
+            match generator.get_pipeline_template(num_nodes) {
+                Ok(result) => {
+                    if best
+                        .as_ref()
+                        .is_none_or(|(_, best)| self.throughput(&result) > self.throughput(best))
+                    {
+                        best = Some((*microbatch_size, result));
+                    }
+                }
+                // Running out of memory tells more than having too few layers
+                Err(e) => {
+                    if !matches!(error, Some(PlannerError::MemoryExceeded { .. })) {
+                        error = Some(e);
+                    }
+                }
+            }
+        }
+
+        match (best, error) {
+            (Some(best), _) => Ok(best),
+            (None, Some(error)) => Err(error),
+            (None, None) => Err(PlannerError::InfeasibleNodeCount {
+                num_nodes: vec![num_nodes],
+                reason: "no microbatch size is planned".to_string(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::MemoryBudget;
+
+    // Larger microbatches amortize a fixed overhead per layer, at 2 bytes of activations
+    // per sample
+    fn layers(microbatch_size: u32) -> Vec<LayerExecutionResult> {
+        (0..4)
+            .map(|i| LayerExecutionResult {
+                layer_index: i,
+                layer_name: format!("layer{}", i),
+                forward: 0.05 + 0.25 * microbatch_size as f64,
+                backward: 0.05 + 0.25 * microbatch_size as f64,
+                mem_required: 2 * microbatch_size as u64,
+                activation_mem: 2 * microbatch_size as u64,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn profiles() -> Vec<(u32, Vec<LayerExecutionResult>)> {
+        vec![
+            (1, layers(1)),
+            (4, layers(4)),
+            (2, layers(2)),
+            (3, layers(3)),
+        ]
+    }
+
+    #[test]
+    fn test_choose_microbatch_size_with_highest_throughput() {
+        let mut planner = MicrobatchPlanner::new(profiles(), 16, PlannerConfig::default()).unwrap();
+        // 3 does not divide the global batch
+        assert!(planner.generator(3).is_none());
+        assert_eq!(
+            planner.generator(4).unwrap().config().num_microbatches.min,
+            4
+        );
+        planner.divide_and_conquer(2).unwrap();
+
+        // Without a bubble, the largest microbatch amortizes the overhead best
+        let (microbatch_size, template) = planner.get_pipeline_template(1).unwrap();
+        assert_eq!(microbatch_size, 4);
+        assert_eq!(template.stages.len(), 1);
+
+        // Four microbatches of 4 leave two stages idle a fifth of the time
+        let (microbatch_size, template) = planner.get_pipeline_template(2).unwrap();
+        assert_eq!(microbatch_size, 2);
+        assert_eq!(template.stages.len(), 2);
+    }
+
+    #[test]
+    fn test_choose_microbatch_size_that_fits() {
+        // One stage of 4 layers holds activations of a single microbatch with 1F1B
+        let config = |capacity: u64| PlannerConfig {
+            memory_budget: Some(MemoryBudget::new(capacity, 0.0).unwrap()),
+            ..Default::default()
+        };
+        let mut planner = MicrobatchPlanner::new(profiles(), 16, config(20)).unwrap();
+        planner.divide_and_conquer(1).unwrap();
+        let (microbatch_size, template) = planner.get_pipeline_template(1).unwrap();
+        assert_eq!(microbatch_size, 2);
+        assert!(template.peak_memory() <= 20);
+
+        let mut planner = MicrobatchPlanner::new(profiles(), 16, config(4)).unwrap();
+        planner.divide_and_conquer(1).unwrap();
+        assert!(matches!(
+            planner.get_pipeline_template(1),
+            Err(PlannerError::MemoryExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_return_error_for_invalid_microbatch_sizes() {
+        let config = PlannerConfig::default();
+        assert!(MicrobatchPlanner::new(vec![], 8, config.clone()).is_err());
+        assert!(MicrobatchPlanner::new(vec![(3, layers(3))], 8, config.clone()).is_err());
+        assert!(MicrobatchPlanner::new(vec![(2, layers(2)), (2, layers(2))], 8, config).is_err());
+    }
+}
@@ -1,191 +1,671 @@
+use crate::config::{Cancellation, DeviceClass, LinkSpec, PlannerConfig, SearchMode};
 use crate::execution_result::*;
+use crate::schedule::LatencySummary;
+use crate::validation::{validate_device_class, validate_profile, ProfileError};
 use crate::PlannerError;
-use dashmap::DashMap;
 use log;
 use rayon::prelude::*;
-use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::result::Result;
 use std::sync::Arc;
 
+// Why a subproblem has no pipeline, from the least to the most informative
+#[derive(Clone, Debug, PartialEq)]
+enum Infeasibility {
+    // None of the ways to split the subproblem is feasible
+    NoFeasibleSplit,
+    // Fewer layers than stages
+    TooFewLayers,
+    MemoryExceeded {
+        layers: (u32, u32),
+        required: u64,
+        budget: u64,
+    },
+}
+
+impl Infeasibility {
+    fn rank(&self) -> u32 {
+        match self {
+            Infeasibility::NoFeasibleSplit => 0,
+            Infeasibility::TooFewLayers => 1,
+            Infeasibility::MemoryExceeded { .. } => 2,
+        }
+    }
+
+    /// Whichever of the two tells more about why there is no pipeline.
+    fn or(self, other: Infeasibility) -> Infeasibility {
+        if other.rank() > self.rank() {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn into_planner_error(self, num_nodes: &[u32]) -> PlannerError {
+        let num_nodes = num_nodes.to_vec();
+        match self {
+            Infeasibility::NoFeasibleSplit => PlannerError::InfeasibleNodeCount {
+                num_nodes,
+                reason: "no way to split the layers into stages is feasible".to_string(),
+            },
+            Infeasibility::TooFewLayers => PlannerError::InfeasibleNodeCount {
+                num_nodes,
+                reason: "fewer layers than stages".to_string(),
+            },
+            Infeasibility::MemoryExceeded {
+                layers,
+                required,
+                budget,
+            } => PlannerError::MemoryExceeded {
+                num_nodes,
+                layers,
+                required,
+                budget,
+            },
+        }
+    }
+}
+
+// Pipeline over the layers from some layer to the last one, reduced to what it takes
+// to rank it and to put another stage in front of it. Its stages are rebuilt from the
+// backpointers when a template is requested.
+#[derive(Clone, Copy, Debug)]
+struct Candidate {
+    // Latencies of all stages but the first
+    rest: LatencySummary,
+    // Latency of the first stage, which grows once it receives from a stage in front of it
+    head: f64,
+    // Iteration time of the pipeline on its own
+    latency: f64,
+    mem_required: u64,
+    peak_memory: u64,
+    // The first stage covers layers up to `end` on `device_class`, and the rest of the
+    // pipeline is the `next`th candidate of the subproblem starting at `end`
+    end: u32,
+    device_class: u32,
+    checkpointed: bool,
+    next: u32,
+}
+
+// Pipelines kept for a subproblem, ordered from the fastest, or why there is none
+type Candidates = Result<Vec<Candidate>, Infeasibility>;
+
 pub struct PipelineTemplateGenerator {
     pub layer_execution_results: Vec<LayerExecutionResult>,
-    // Key: (layer_start_index, layer_end_index)
-    stage_execution_results: DashMap<(usize, usize), Arc<StageExecutionResult>>,
-    // Key: (num_stages, layer_start_index, layer_end_index)
-    execution_result_cache: DashMap<(u32, usize, usize), Result<PipelineExecutionResult, String>>,
+    config: PlannerConfig,
+    // Device classes stages can be placed on. A single class for homogeneous clusters.
+    device_classes: Vec<DeviceClass>,
+    // Largest number of stages on each device class the cache covers
+    max_num_stages: Vec<u32>,
+    // Costs of the layers on each device class, from which stages are made
+    stage_costs: Vec<StageCosts>,
+    // Key: number of stages on each device class. Indexed by the first layer covered;
+    // pipelines always cover the layers from there to the last one.
+    execution_result_cache: HashMap<Vec<u32>, Vec<Candidates>>,
 }
 
 impl PipelineTemplateGenerator {
-    pub fn new(profile_data: Vec<LayerExecutionResult>) -> Self {
+    pub fn new(mut profile_data: Vec<LayerExecutionResult>, config: PlannerConfig) -> Self {
+        let mut device_classes = if config.device_classes.is_empty() {
+            vec![DeviceClass::default()]
+        } else {
+            config.device_classes.clone()
+        };
+
+        if let Some(memory_model) = &config.memory_model {
+            profile_data
+                .iter_mut()
+                .chain(
+                    device_classes
+                        .iter_mut()
+                        .filter_map(|class| class.profile.as_mut())
+                        .flatten(),
+                )
+                .for_each(|layer| memory_model.apply(layer));
+        }
+
         PipelineTemplateGenerator {
             layer_execution_results: profile_data,
-            stage_execution_results: DashMap::new(),
-            execution_result_cache: DashMap::new(),
+            config,
+            device_classes,
+            max_num_stages: vec![],
+            stage_costs: vec![],
+            execution_result_cache: HashMap::new(),
         }
     }
 
+    pub fn config(&self) -> &PlannerConfig {
+        &self.config
+    }
+
+    /// Replaces the cancellation planning checks, e.g. with a new timeout for the next call.
+    pub fn set_cancellation(&mut self, cancellation: Cancellation) {
+        self.config.cancellation = cancellation;
+    }
+
+    pub fn device_classes(&self) -> &[DeviceClass] {
+        &self.device_classes
+    }
+
     pub fn divide_and_conquer(&mut self, max_num_nodes: u32) -> Result<(), PlannerError> {
-        if !self.stage_execution_results.is_empty() {
-            return Ok(());
-        }
+        self.divide_and_conquer_heterogeneous(&[max_num_nodes])
+    }
 
+    /// Fills the cache for every multiset of nodes with at most `max_num_nodes[c]` nodes
+    /// of device class `c`.
+    ///
+    /// Pipelines planned by earlier calls are kept, so that planning for more nodes only
+    /// adds the pipelines with more stages, e.g. once the cluster has grown. This includes
+    /// calls that were cancelled, which keep every pipeline planned before cancellation.
+    pub fn divide_and_conquer_heterogeneous(
+        &mut self,
+        max_num_nodes: &[u32],
+    ) -> Result<(), PlannerError> {
         let num_layers = self.layer_execution_results.len();
 
-        if max_num_nodes as usize > num_layers {
-            return Err(PlannerError::new("Invalid number of nodes"));
-        }
-
-        // Put all base cases in the cache
-        (0..num_layers).into_par_iter().for_each(|i| {
-            ((i + 1)..=num_layers).into_par_iter().for_each(|j| {
-                let stage_execution_result = Arc::new(StageExecutionResult::new(
-                    &self.layer_execution_results[i..j],
-                ));
-                log::debug!(
-                    "StageExecutionResult({}, {})  -> {}",
-                    stage_execution_result.layers.0,
-                    stage_execution_result.layers.1,
-                    stage_execution_result.latency()
-                );
-                self.stage_execution_results
-                    .insert((i, j), stage_execution_result.clone());
-
-                let pipeline_execution_result =
-                    PipelineExecutionResult::make_base_result(stage_execution_result);
-                log::debug!(
-                    "PipelineExecutionResult({}, {}, {}) -> {}",
-                    1,
-                    i,
-                    j,
-                    pipeline_execution_result.latency()
-                );
-                self.execution_result_cache
-                    .insert((1, i, j), Ok(pipeline_execution_result));
+        if max_num_nodes.len() != self.device_classes.len() {
+            return Err(PlannerError::invalid_config(format!(
+                "Expected numbers of nodes for {} device classes, got {}",
+                self.device_classes.len(),
+                max_num_nodes.len()
+            )));
+        }
+        validate_profile(&self.layer_execution_results)?;
+        for class in self.device_classes.iter() {
+            validate_device_class(class)?;
+            if let Some(profile) = &class.profile {
+                if profile.len() != num_layers {
+                    return Err(ProfileError::InvalidDeviceClass {
+                        name: class.name.clone(),
+                        reason: format!(
+                            "profile has {} layers, expected {}",
+                            profile.len(),
+                            num_layers
+                        ),
+                    }
+                    .into());
+                }
+            }
+        }
+
+        let stages_per_node = self.config.gpus_per_node;
+        if stages_per_node == 0 {
+            return Err(PlannerError::invalid_config(
+                "A node must have at least one GPU",
+            ));
+        }
+        if stages_per_node > 1 && self.device_classes.len() > 1 {
+            return Err(PlannerError::invalid_config(
+                "Multiple GPUs per node are not supported with multiple device classes",
+            ));
+        }
+
+        let max_num_stages: Vec<u32> = max_num_nodes
+            .iter()
+            .map(|num_nodes| num_nodes * stages_per_node)
+            .collect();
+        let total_num_stages: u32 = max_num_stages.iter().sum();
+
+        if total_num_stages as usize > num_layers {
+            return Err(PlannerError::InfeasibleNodeCount {
+                num_nodes: max_num_nodes.to_vec(),
+                reason: format!(
+                    "{} stages are more than {} layers",
+                    total_num_stages, num_layers
+                ),
             });
-        });
+        }
+
+        // Cover the multisets of earlier calls as well
+        self.max_num_stages = max_num_stages
+            .iter()
+            .enumerate()
+            .map(|(c, &max)| max.max(self.max_num_stages.get(c).copied().unwrap_or(0)))
+            .collect();
+        log::debug!(
+            "Planning up to {:?} stages with the {} schedule",
+            self.max_num_stages,
+            self.config.schedule.name()
+        );
+
+        if self.stage_costs.is_empty() {
+            self.stage_costs = self
+                .device_classes
+                .iter()
+                .map(|class| match &class.profile {
+                    Some(profile) => StageCosts::new(profile),
+                    None => StageCosts::new(&self.layer_execution_results),
+                })
+                .collect();
+        }
+
+        // A pipeline of s stages over layers i.. is a first stage over layers i..j put in
+        // front of a pipeline of s - 1 stages over layers j.., so pipelines are computed
+        // with gradually more stages, all pipelines of s - 1 stages before those of s.
+        // Combined, earlier calls may cover more stages than layers; pipelines of that
+        // many stages are left out.
+        let max_total_num_stages = num_layers as u32;
+        let new_stage_counts: Vec<Vec<u32>> = Self::multisets(&self.max_num_stages)
+            .into_iter()
+            .filter(|stage_counts| {
+                stage_counts.iter().sum::<u32>() <= max_total_num_stages
+                    && !self.execution_result_cache.contains_key(stage_counts)
+            })
+            .collect();
+        for num_stages in 1..=max_total_num_stages {
+            for stage_counts in new_stage_counts
+                .iter()
+                .filter(|stage_counts| stage_counts.iter().sum::<u32>() == num_stages)
+            {
+                self.config.cancellation.check()?;
+                let results: Vec<Candidates> = (0..num_layers)
+                    .into_par_iter()
+                    .map(|i| self.prepend_stage(stage_counts, i))
+                    .collect();
+                match &results[0] {
+                    Ok(candidates) => log::debug!(
+                        "Pipeline of {:?} stages over all layers: latency {}, peak memory {}",
+                        stage_counts,
+                        candidates[0].latency,
+                        candidates[0].peak_memory
+                    ),
+                    Err(e) => log::debug!(
+                        "Pipeline of {:?} stages over all layers is infeasible: {:?}",
+                        stage_counts,
+                        e
+                    ),
+                }
+                self.execution_result_cache
+                    .insert(stage_counts.clone(), results);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pipelines with `stage_counts[c]` stages on device class `c` over layers `i..`,
+    /// trying every first stage in front of the pipelines of the remaining stages.
+    fn prepend_stage(&self, stage_counts: &[u32], i: usize) -> Candidates {
+        let num_layers = self.layer_execution_results.len();
+        let num_stages: u32 = stage_counts.iter().sum();
+        if num_layers - i < num_stages as usize {
+            return Err(Infeasibility::TooFewLayers);
+        }
 
-        log::debug!("Base cases inserted into the cache");
-
-        // Compute the rest of the results, gradually increasing the number of stages
-        // Number of stages can increase from 2 up to the number of nodes
-        // (currently more than two stages cannot be assigned to a node)
-        // Each number of stages all computations should be done before moving on to the next number of stages
-        for num_stages in 2..=max_num_nodes as u32 {
-            (0..num_layers).into_par_iter().for_each(|i| {
-                ((i + 1)..=num_layers).into_par_iter().for_each(|j| {
-                    let key = (num_stages, i, j);
-
-                    // If number of layers is less than number of stages, skip it
-                    // Cannot create specified number of stages with the given number of layers
-                    if j - i < num_stages as usize {
-                        self.execution_result_cache
-                            .insert(key, Err("Infeasible case".to_string()));
-                        return;
+        // The first stage keeps activations for as long as the stages after it
+        let in_flight = self.config.schedule.in_flight_microbatches(
+            0,
+            num_stages as usize,
+            self.config.num_microbatches.max,
+        );
+        let link = self.link(num_stages - 1);
+
+        let mut candidates: Vec<Candidate> = vec![];
+        let mut error = Infeasibility::NoFeasibleSplit;
+        for device_class in 0..stage_counts.len() {
+            if stage_counts[device_class] == 0 {
+                continue;
+            }
+
+            // A single stage covers all remaining layers
+            if num_stages == 1 {
+                match self.fit_stage(device_class, i, num_layers, in_flight) {
+                    Ok((stage, memory)) => candidates.push(Candidate {
+                        rest: LatencySummary::default(),
+                        head: stage.latency(),
+                        latency: self.iteration_time(&LatencySummary::default(), stage.latency()),
+                        mem_required: memory,
+                        peak_memory: memory,
+                        end: num_layers as u32,
+                        device_class: device_class as u32,
+                        checkpointed: stage.checkpointed,
+                        next: 0,
+                    }),
+                    Err(e) => error = error.or(e),
+                }
+                continue;
+            }
+
+            let mut rest_counts = stage_counts.to_vec();
+            rest_counts[device_class] -= 1;
+            let rest = &self.execution_result_cache[&rest_counts];
+
+            // Leave at least one layer for each of the remaining stages
+            for j in (i + 1)..=(num_layers - num_stages as usize + 1) {
+                let rest = match &rest[j] {
+                    Ok(rest) => rest,
+                    Err(e) => {
+                        error = error.or(e.clone());
+                        continue;
                     }
+                };
+                let (stage, memory) = match self.fit_stage(device_class, i, j, in_flight) {
+                    Ok(stage) => stage,
+                    Err(e) => {
+                        error = error.or(e);
+                        continue;
+                    }
+                };
 
-                    // Spawn a task to compute the result for this subproblem.
-                    let best_result = (i..j)
-                        .into_par_iter()
-                        .map(|num_layers_left| {
-                            let mut result: Result<PipelineExecutionResult, String> =
-                                Err("Error in subproblem".to_string());
-
-                            for num_stages_left in 1..num_stages {
-                                let num_stages_right = num_stages - num_stages_left;
-
-                                if num_layers_left - i == 0 || j - num_layers_left == 0 {
-                                    continue;
-                                }
-
-                                // As we gradually increase the number of stages from 1,
-                                // we must have already computed the results for the subproblems
-                                let left = self
-                                    .execution_result_cache
-                                    .get(&(num_stages_left, i, num_layers_left))
-                                    .unwrap();
-                                let right = self
-                                    .execution_result_cache
-                                    .get(&(num_stages_right, num_layers_left, j))
-                                    .unwrap();
-
-                                if left.is_err() || right.is_err() {
-                                    continue;
-                                }
-
-                                // Merge two subproblems into a bigger PipelineExecutionResult
-                                let local_result = PipelineExecutionResult::new(
-                                    left.as_ref().unwrap(),
-                                    right.as_ref().unwrap(),
-                                );
-                                if result.is_err()
-                                    || local_result.cmp(result.as_ref().unwrap()) == Ordering::Less
-                                {
-                                    result = Ok(local_result);
-                                }
-                            }
-
-                            result
-                        })
-                        .reduce(
-                            || Err("Error in subproblem".to_string()),
-                            |acc, result| {
-                                if result.is_err() {
-                                    return acc;
-                                } else if acc.is_err() {
-                                    return result;
-                                } else if result.as_ref().unwrap() < acc.as_ref().unwrap() {
-                                    return result;
-                                } else {
-                                    return acc;
-                                }
-                            },
-                        );
+                // Activations and gradients occupy both stages at the boundary
+                let boundary = link.round_trip_time(stage.output_activation_size);
+                for (next, rest) in rest.iter().enumerate() {
+                    let head = stage.latency() + boundary;
+                    let latencies = rest.rest.prepend(rest.head + boundary);
+                    candidates.push(Candidate {
+                        rest: latencies,
+                        head,
+                        latency: self.iteration_time(&latencies, head),
+                        mem_required: rest.mem_required + memory,
+                        peak_memory: rest.peak_memory.max(memory),
+                        end: j as u32,
+                        device_class: device_class as u32,
+                        checkpointed: stage.checkpointed,
+                        next: next as u32,
+                    });
+                }
+            }
+        }
 
-                    log::debug!(
-                        "PipelineExecutionResult({}, {}, {}) -> {}",
-                        num_stages,
-                        i,
-                        j,
-                        if best_result.is_ok() {
-                            best_result.as_ref().unwrap().latency()
-                        } else {
-                            0.0
-                        }
-                    );
-                    self.execution_result_cache.insert(key, best_result);
+        if candidates.is_empty() {
+            Err(error)
+        } else {
+            Ok(self.prune(candidates))
+        }
+    }
+
+    /// Iteration time of a pipeline with a stage of latency `head` in front of `rest`.
+    fn iteration_time(&self, rest: &LatencySummary, head: f64) -> f64 {
+        let (t1, t2, t3) = self
+            .config
+            .schedule
+            .iteration_time(&rest.prepend(head), self.config.num_microbatches.mean());
+        t1 + t2 + t3
+    }
+
+    /// Link a stage sends its activations over when `num_stages` stages follow it.
+    /// Pipelines span whole nodes, so the next stage is on another node
+    /// if the stages that follow span whole nodes as well.
+    fn link(&self, num_stages: u32) -> &LinkSpec {
+        if num_stages.is_multiple_of(self.config.gpus_per_node) {
+            &self.config.link
+        } else {
+            &self.config.intra_node_link
+        }
+    }
+
+    /// Stage of layers `i..j` on the given device class.
+    fn make_stage(&self, device_class: usize, i: usize, j: usize) -> StageExecutionResult {
+        let class = &self.device_classes[device_class];
+        let speed = match &class.profile {
+            Some(_) => 1.0,
+            None => class.speed,
+        };
+        self.stage_costs[device_class]
+            .stage(i, j)
+            .on_device(device_class, speed)
+            .with_margin(self.config.latency_statistic.z_score())
+    }
+
+    /// Stage of layers `i..j` keeping activations of `in_flight` microbatches, along with
+    /// its peak memory. Activation checkpointing is enabled if it does not fit otherwise.
+    fn fit_stage(
+        &self,
+        device_class: usize,
+        i: usize,
+        j: usize,
+        in_flight: f64,
+    ) -> Result<(StageExecutionResult, u64), Infeasibility> {
+        let stage = self.make_stage(device_class, i, j);
+        let memory = stage.peak_memory(in_flight);
+        let limit = match self.memory_limit(device_class) {
+            Some(limit) if memory > limit => limit,
+            _ => return Ok((stage, memory)),
+        };
+
+        let stage = if stage.can_checkpoint() {
+            stage.with_checkpointing()
+        } else {
+            stage
+        };
+        let memory = stage.peak_memory(in_flight);
+        if memory > limit {
+            Err(Infeasibility::MemoryExceeded {
+                layers: stage.layers,
+                required: memory,
+                budget: limit,
+            })
+        } else {
+            Ok((stage, memory))
+        }
+    }
+
+    /// All vectors of counts that are elementwise at most `max_counts`.
+    fn multisets(max_counts: &[u32]) -> Vec<Vec<u32>> {
+        max_counts.iter().fold(vec![vec![]], |multisets, &max| {
+            multisets
+                .into_iter()
+                .flat_map(|multiset| {
+                    (0..=max).map(move |count| {
+                        let mut multiset = multiset.clone();
+                        multiset.push(count);
+                        multiset
+                    })
                 })
-            });
+                .collect()
+        })
+    }
+
+    /// Usable memory of a device of the given class, if limited.
+    fn memory_limit(&self, device_class: usize) -> Option<u64> {
+        self.device_classes[device_class]
+            .memory_budget
+            .or(self.config.memory_budget)
+            .map(|budget| budget.usable())
+    }
+
+    /// Enables activation checkpointing on stages that do not fit in device memory,
+    /// and rejects the result if any stage still does not fit.
+    ///
+    /// Stages are fitted as they are planned, but with interleaved schedules the memory
+    /// of a stage also depends on the stages in front of it.
+    fn fit_memory(
+        &self,
+        result: PipelineExecutionResult,
+    ) -> Result<PipelineExecutionResult, Infeasibility> {
+        let limits: Vec<Option<u64>> = result
+            .stages
+            .iter()
+            .map(|stage| self.memory_limit(stage.device_class))
+            .collect();
+        if limits.iter().all(Option::is_none) {
+            return Ok(result);
         }
-        Ok(())
+        let exceeds = |k: usize, mem: u64| limits[k].is_some_and(|limit| mem > limit);
+
+        let to_checkpoint: Vec<usize> = (0..result.stages.len())
+            .filter(|&k| exceeds(k, result.stage_memory[k]) && result.stages[k].can_checkpoint())
+            .collect();
+        let result = if to_checkpoint.is_empty() {
+            result
+        } else {
+            let mut stages = result.stages.clone();
+            for k in to_checkpoint {
+                stages[k] = Arc::new(stages[k].with_checkpointing());
+            }
+            PipelineExecutionResult::from_stages(stages, result.comm, &self.config)
+        };
+
+        match (0..result.stages.len()).find(|&k| exceeds(k, result.stage_memory[k])) {
+            Some(index) => Err(Infeasibility::MemoryExceeded {
+                layers: result.stages[index].layers,
+                required: result.stage_memory[index],
+                budget: limits[index].unwrap(),
+            }),
+            None => Ok(result),
+        }
+    }
+
+    /// Orders candidates from the fastest and drops those the search mode does not keep.
+    fn prune(&self, mut candidates: Vec<Candidate>) -> Vec<Candidate> {
+        // Ties are broken by the memory the search mode ranks by
+        let pareto = self.config.search == SearchMode::ParetoFrontier;
+        candidates.sort_by(|a, b| {
+            a.latency.total_cmp(&b.latency).then_with(|| {
+                if pareto {
+                    a.peak_memory.cmp(&b.peak_memory)
+                } else {
+                    a.mem_required.cmp(&b.mem_required)
+                }
+            })
+        });
+        match self.config.search {
+            SearchMode::Best => candidates.truncate(1),
+            SearchMode::ParetoFrontier => {
+                // Every kept candidate needs less memory than all faster ones
+                let mut min_peak_memory = u64::MAX;
+                candidates.retain(|candidate| {
+                    let keep = candidate.peak_memory < min_peak_memory;
+                    min_peak_memory = min_peak_memory.min(candidate.peak_memory);
+                    keep
+                });
+            }
+            // Candidates differ in their first stage or in the rest of the pipeline,
+            // so they are distinct templates
+            SearchMode::TopK(k) => candidates.truncate(k),
+        }
+        candidates
+    }
+
+    /// Stages of the pipeline the candidate stands for, following the backpointers.
+    fn rebuild(
+        &self,
+        stage_counts: &[u32],
+        candidate: &Candidate,
+    ) -> Result<PipelineExecutionResult, Infeasibility> {
+        let num_stages = stage_counts.iter().sum::<u32>() as usize;
+        let mut stage_counts = stage_counts.to_vec();
+        let mut stages: Vec<Arc<StageExecutionResult>> = Vec::with_capacity(num_stages);
+        let mut comm = vec![0.0; num_stages];
+
+        let mut candidate = *candidate;
+        let mut start = 0;
+        for k in 0..num_stages {
+            let device_class = candidate.device_class as usize;
+            let stage = self.make_stage(device_class, start, candidate.end as usize);
+            let stage = if candidate.checkpointed {
+                stage.with_checkpointing()
+            } else {
+                stage
+            };
+            stage_counts[device_class] -= 1;
+            start = candidate.end as usize;
+
+            if k + 1 < num_stages {
+                let boundary = self
+                    .link((num_stages - k - 1) as u32)
+                    .round_trip_time(stage.output_activation_size);
+                comm[k] += boundary;
+                comm[k + 1] += boundary;
+                candidate = match &self.execution_result_cache[&stage_counts][start] {
+                    Ok(candidates) => candidates[candidate.next as usize],
+                    Err(_) => unreachable!("candidates only point to feasible pipelines"),
+                };
+            }
+            stages.push(Arc::new(stage));
+        }
+
+        self.fit_memory(PipelineExecutionResult::from_stages(
+            stages,
+            comm,
+            &self.config,
+        ))
     }
 
     pub fn get_pipeline_template(
         &self,
         num_nodes: u32,
     ) -> Result<PipelineExecutionResult, PlannerError> {
+        self.get_heterogeneous_pipeline_template(&[num_nodes])
+    }
+
+    /// All templates the search mode keeps for the given number of nodes, from the fastest.
+    pub fn get_pipeline_templates(
+        &self,
+        num_nodes: u32,
+    ) -> Result<Vec<PipelineExecutionResult>, PlannerError> {
+        self.get_heterogeneous_pipeline_templates(&[num_nodes])
+    }
+
+    /// Template with exactly `num_nodes[c]` nodes of device class `c`.
+    pub fn get_heterogeneous_pipeline_template(
+        &self,
+        num_nodes: &[u32],
+    ) -> Result<PipelineExecutionResult, PlannerError> {
+        Ok(self
+            .get_heterogeneous_pipeline_templates(num_nodes)?
+            .swap_remove(0))
+    }
+
+    pub fn get_heterogeneous_pipeline_templates(
+        &self,
+        num_nodes: &[u32],
+    ) -> Result<Vec<PipelineExecutionResult>, PlannerError> {
         log::debug!(
-            "get_pipeline_template({}, {}, {})",
+            "get_pipeline_template({:?}, {}, {})",
             num_nodes,
             0,
             self.layer_execution_results.len()
         );
 
-        let result =
-            self.execution_result_cache
-                .get(&(num_nodes, 0, self.layer_execution_results.len()));
+        let stage_counts: Vec<u32> = num_nodes
+            .iter()
+            .map(|num_nodes| num_nodes * self.config.gpus_per_node)
+            .collect();
 
-        match result {
-            Some(result) => Ok(result.value().clone().unwrap()),
-            None => Err(PlannerError::new(
-                format!("No pipeline template for {} nodes", num_nodes).as_str(),
-            ))?,
+        let candidates = match self.execution_result_cache.get(&stage_counts) {
+            Some(results) => match &results[0] {
+                Ok(candidates) => candidates,
+                Err(e) => return Err(e.clone().into_planner_error(num_nodes)),
+            },
+            None => {
+                return Err(PlannerError::InfeasibleNodeCount {
+                    num_nodes: num_nodes.to_vec(),
+                    reason: "beyond the numbers of nodes planned for".to_string(),
+                })
+            }
+        };
+
+        let mut results: Vec<PipelineExecutionResult> = Vec::with_capacity(candidates.len());
+        let mut error = Infeasibility::NoFeasibleSplit;
+        for candidate in candidates.iter() {
+            match self.rebuild(&stage_counts, candidate) {
+                Ok(result) => {
+                    log::debug!(
+                        "PipelineExecutionResult({:?}) -> {}, {} bytes at peak",
+                        stage_counts,
+                        result.latency(),
+                        result.peak_memory()
+                    );
+                    results.push(result);
+                }
+                Err(e) => error = error.or(e),
+            }
+        }
+        if results.is_empty() {
+            return Err(error.into_planner_error(num_nodes));
         }
+        results.sort();
+        Ok(results)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::config::{LatencyStatistic, LinkSpec, MemoryBudget, MicrobatchRange};
+    use crate::schedule::{GPipe, Interleaved1F1B, OneForwardOneBackward};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
 
     fn prepare(
         num_layers: u32,
@@ -212,16 +692,31 @@ mod test {
                 } else {
                     (i + 1) as u64
                 },
+                ..Default::default()
             });
         }
 
         num_nodes.sort();
 
-        let mut generator = PipelineTemplateGenerator::new(layer_results);
+        let mut generator = PipelineTemplateGenerator::new(layer_results, PlannerConfig::default());
         generator.divide_and_conquer(num_nodes[num_nodes.len() - 1])?;
         Ok(generator)
     }
 
+    fn uniform_layers(num_layers: u32, activation_size: u64) -> Vec<LayerExecutionResult> {
+        (0..num_layers)
+            .map(|i| LayerExecutionResult {
+                layer_index: i,
+                layer_name: format!("layer{}", i),
+                forward: 1.0,
+                backward: 1.0,
+                mem_required: 1,
+                activation_size,
+                ..Default::default()
+            })
+            .collect()
+    }
+
     #[test]
     fn test_return_no_template_for_too_large_num_nodes() {
         let generator = prepare(6, true, vec![7]);
@@ -302,6 +797,616 @@ mod test {
         assert_eq!(template.stages[3].layers, (5, 6));
     }
 
+    #[test]
+    fn test_extend_to_more_nodes() {
+        let mut generator = prepare(6, false, vec![2]).unwrap();
+        assert!(generator.get_pipeline_template(3).is_err());
+
+        generator.divide_and_conquer(4).unwrap();
+        let fresh = prepare(6, false, vec![4]).unwrap();
+        for num_nodes in 1..=4 {
+            assert_eq!(
+                generator
+                    .get_pipeline_template(num_nodes)
+                    .unwrap()
+                    .latency(),
+                fresh.get_pipeline_template(num_nodes).unwrap().latency()
+            );
+        }
+
+        // Neither fewer nodes nor infeasible ones drop what was planned
+        generator.divide_and_conquer(1).unwrap();
+        assert!(generator.divide_and_conquer(7).is_err());
+        assert_eq!(generator.get_pipeline_template(4).unwrap().stages.len(), 4);
+
+        let config = PlannerConfig {
+            device_classes: vec![DeviceClass::new("a100", 1.0), DeviceClass::new("h100", 2.0)],
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(uniform_layers(6, 0), config);
+        generator.divide_and_conquer_heterogeneous(&[1, 0]).unwrap();
+        generator.divide_and_conquer_heterogeneous(&[0, 1]).unwrap();
+        let template = generator
+            .get_heterogeneous_pipeline_template(&[1, 1])
+            .unwrap();
+        assert_eq!(template.stages.len(), 2);
+    }
+
+    #[test]
+    fn test_cancel_planning() {
+        let token = Arc::new(AtomicBool::new(false));
+        let config = PlannerConfig {
+            cancellation: Cancellation::new(token.clone(), None),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(uniform_layers(6, 0), config);
+        generator.divide_and_conquer(2).unwrap();
+
+        // Planning more stages stops, but keeps the pipelines planned before
+        token.store(true, Ordering::Relaxed);
+        assert!(matches!(
+            generator.divide_and_conquer(4),
+            Err(PlannerError::Cancelled(_))
+        ));
+        assert_eq!(generator.get_pipeline_template(2).unwrap().stages.len(), 2);
+
+        generator.set_cancellation(Cancellation::new(token.clone(), Some(Duration::ZERO)));
+        token.store(false, Ordering::Relaxed);
+        assert_eq!(
+            generator.divide_and_conquer(4),
+            Err(PlannerError::Cancelled("timed out after 0ns".to_string()))
+        );
+
+        generator.set_cancellation(Cancellation::default());
+        generator.divide_and_conquer(4).unwrap();
+        assert_eq!(generator.get_pipeline_template(4).unwrap().stages.len(), 4);
+    }
+
+    #[test]
+    fn test_divide_and_conquer_avoids_large_activations() {
+        let mut layer_results = uniform_layers(6, 0);
+        // Sending the output of layer 2 takes 10ms over a 1GB/s link
+        layer_results[2].activation_size = 10_000_000;
+        // A slower last layer makes the split after layer 3 faster than after layer 1
+        layer_results[5].forward = 1.5;
+
+        let config = PlannerConfig {
+            link: LinkSpec::new(1.0, 0.0),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(2).unwrap();
+
+        // Without communication cost the even split (0, 3), (3, 6) would be chosen
+        let template = generator.get_pipeline_template(2).unwrap();
+        assert_eq!(template.stages.len(), 2);
+        assert_eq!(template.stages[0].layers, (0, 4));
+        assert_eq!(template.stages[1].layers, (4, 6));
+    }
+
+    #[test]
+    fn test_communication_added_to_latency() {
+        let config = PlannerConfig {
+            link: LinkSpec::new(1.0, 0.5),
+            ..Default::default()
+        };
+
+        let mut free = PipelineTemplateGenerator::new(uniform_layers(4, 0), config.clone());
+        free.divide_and_conquer(2).unwrap();
+        let mut costly = PipelineTemplateGenerator::new(uniform_layers(4, 1_000_000), config);
+        costly.divide_and_conquer(2).unwrap();
+
+        let free = free.get_pipeline_template(2).unwrap();
+        let costly = costly.get_pipeline_template(2).unwrap();
+
+        // 0.5ms latency + 1ms transfer each way for each boundary stage
+        assert_eq!(free.comm, vec![1.0, 1.0]);
+        assert_eq!(costly.comm, vec![3.0, 3.0]);
+        assert!(costly.latency() > free.latency());
+        assert_eq!(costly.stage_latency(costly.kstar), 7.0);
+    }
+
+    #[test]
+    fn test_latency_with_configured_microbatches() {
+        let layer_results = uniform_layers(6, 0);
+        let config = PlannerConfig {
+            num_microbatches: MicrobatchRange::exact(16).unwrap(),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(2).unwrap();
+
+        // Two stages of 6ms each: (16 + 2 - 1) * 6
+        let template = generator.get_pipeline_template(2).unwrap();
+        assert_eq!(template.latency(), 102.0);
+        let schedule = OneForwardOneBackward;
+        assert_eq!(template.latency_with_mb(16, &schedule), template.latency());
+        assert_eq!(
+            template.latency_with_mb(17, &schedule) - template.latency_with_mb(16, &schedule),
+            template.stage_latency(template.kstar)
+        );
+    }
+
+    #[test]
+    fn test_latency_follows_schedule() {
+        let mut layer_results = uniform_layers(4, 0);
+        layer_results[0].forward = 2.0;
+        let config = PlannerConfig {
+            num_microbatches: MicrobatchRange::exact(8).unwrap(),
+            schedule: Arc::new(GPipe),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(2).unwrap();
+
+        // Stages of 5ms and 4ms: 8 microbatches through the first stage, then drain
+        let template = generator.get_pipeline_template(2).unwrap();
+        assert_eq!(template.stages[0].layers, (0, 2));
+        assert_eq!(template.kstar, 0);
+        assert_eq!(template.latency(), 8.0 * 5.0 + 4.0);
+
+        // 1F1B has the same bubble and only keeps fewer activations, while
+        // interleaving halves the bubble
+        assert_eq!(
+            template.latency_with_mb(8, &OneForwardOneBackward),
+            template.latency()
+        );
+        let interleaved = Interleaved1F1B { num_chunks: 2 };
+        assert_ne!(
+            template.latency_with_mb(8, &interleaved),
+            template.latency()
+        );
+        assert_eq!(
+            template.latency_with_mb(8, &interleaved),
+            8.0 * 5.0 + 4.0 / 2.0
+        );
+    }
+
+    #[test]
+    fn test_more_stages_than_microbatches() {
+        let layer_results = uniform_layers(6, 0);
+        let config = PlannerConfig {
+            num_microbatches: MicrobatchRange::exact(2).unwrap(),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(3).unwrap();
+
+        // Stages of 4ms: two microbatches through the last stage after a bubble of two
+        let template = generator.get_pipeline_template(3).unwrap();
+        assert_eq!(template.stages.len(), 3);
+        assert_eq!(template.latency(), 2.0 * 4.0 + 2.0 * 4.0);
+    }
+
+    #[test]
+    fn test_stage_memory_depends_on_position() {
+        let mut layer_results = uniform_layers(6, 0);
+        for layer in layer_results.iter_mut() {
+            layer.param_mem = 10;
+            layer.activation_mem = 1;
+        }
+        let config = PlannerConfig {
+            num_microbatches: MicrobatchRange::new(8, 16).unwrap(),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(3).unwrap();
+
+        // Two layers per stage, and stage i keeps activations of 3 - i microbatches
+        let template = generator.get_pipeline_template(3).unwrap();
+        assert_eq!(template.stage_memory, vec![26, 24, 22]);
+        assert_eq!(template.peak_memory(), 26);
+        assert_eq!(template.mem_required(), 72);
+
+        // Profiles without a memory breakdown keep activations out of the picture
+        let generator = prepare(6, true, vec![3]).unwrap();
+        let template = generator.get_pipeline_template(3).unwrap();
+        assert_eq!(template.stage_memory, vec![2, 2, 2]);
+    }
+
+    #[test]
+    fn test_gpipe_keeps_all_microbatches() {
+        let mut layer_results = uniform_layers(4, 0);
+        for layer in layer_results.iter_mut() {
+            layer.param_mem = 10;
+            layer.activation_mem = 1;
+        }
+        let config = PlannerConfig {
+            num_microbatches: MicrobatchRange::new(8, 16).unwrap(),
+            schedule: Arc::new(GPipe),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(2).unwrap();
+
+        let template = generator.get_pipeline_template(2).unwrap();
+        assert_eq!(template.stage_memory, vec![52, 52]);
+    }
+
+    #[test]
+    fn test_divide_and_conquer_respects_memory_budget() {
+        let mut layer_results = uniform_layers(6, 0);
+        for layer in layer_results.iter_mut() {
+            layer.param_mem = 1;
+            layer.activation_mem = 4;
+        }
+        // The first stage keeps two microbatches, so it can hold at most two layers
+        let config = PlannerConfig {
+            memory_budget: Some(MemoryBudget::new(52, 0.5).unwrap()),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(2).unwrap();
+
+        let template = generator.get_pipeline_template(2).unwrap();
+        assert_eq!(template.stages[0].layers, (0, 2));
+        assert_eq!(template.stages[1].layers, (2, 6));
+        assert_eq!(template.stage_memory, vec![18, 20]);
+    }
+
+    #[test]
+    fn test_return_error_for_invalid_memory_headroom() {
+        for headroom in [-0.1, 1.0, f64::NAN] {
+            assert!(matches!(
+                MemoryBudget::new(52, headroom),
+                Err(PlannerError::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_return_error_for_stages_exceeding_memory_budget() {
+        let mut layer_results = uniform_layers(6, 0);
+        for layer in layer_results.iter_mut() {
+            layer.param_mem = 1;
+            layer.activation_mem = 4;
+        }
+        let config = PlannerConfig {
+            memory_budget: Some(MemoryBudget::new(5, 0.0).unwrap()),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(2).unwrap();
+
+        for num_nodes in 1..=2 {
+            let error = generator.get_pipeline_template(num_nodes).err().unwrap();
+            assert!(matches!(
+                error,
+                PlannerError::MemoryExceeded { budget: 5, .. }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_checkpoint_only_stages_exceeding_memory_budget() {
+        let mut layer_results = uniform_layers(4, 0);
+        for layer in layer_results.iter_mut() {
+            layer.param_mem = 1;
+            layer.activation_mem = 4;
+            layer.recompute = 1.0;
+            layer.checkpoint_mem_saved = 3;
+        }
+        // Without checkpointing, stages need 18 and 10 bytes
+        let config = PlannerConfig {
+            memory_budget: Some(MemoryBudget::new(12, 0.0).unwrap()),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(2).unwrap();
+
+        let template = generator.get_pipeline_template(2).unwrap();
+        assert_eq!(template.stages[0].layers, (0, 2));
+        assert!(template.stages[0].checkpointed);
+        assert!(!template.stages[1].checkpointed);
+        assert_eq!(template.stage_memory, vec![6, 10]);
+        assert_eq!(template.stage_latency(0), 6.0);
+
+        // A single stage fits only if checkpointed
+        let template = generator.get_pipeline_template(1).unwrap();
+        assert!(template.stages[0].checkpointed);
+        assert_eq!(template.stage_memory, vec![8]);
+    }
+
+    #[test]
+    fn test_heterogeneous_heavier_stage_on_faster_device() {
+        let config = PlannerConfig {
+            device_classes: vec![DeviceClass::new("a100", 1.0), DeviceClass::new("h100", 2.0)],
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(uniform_layers(6, 0), config);
+        generator.divide_and_conquer_heterogeneous(&[1, 1]).unwrap();
+
+        // 2ms per layer on a100 and 1ms on h100: 2 layers on a100, 4 layers on h100
+        let template = generator
+            .get_heterogeneous_pipeline_template(&[1, 1])
+            .unwrap();
+        assert_eq!(template.stages.len(), 2);
+        let (a100, h100) = if template.stages[0].device_class == 0 {
+            (&template.stages[0], &template.stages[1])
+        } else {
+            (&template.stages[1], &template.stages[0])
+        };
+        assert_eq!(a100.layers.1 - a100.layers.0, 2);
+        assert_eq!(h100.device_class, 1);
+        assert_eq!(h100.layers.1 - h100.layers.0, 4);
+        assert_eq!(a100.latency(), h100.latency());
+
+        // Subsets of the multiset are planned as well
+        let template = generator
+            .get_heterogeneous_pipeline_template(&[0, 1])
+            .unwrap();
+        assert_eq!(template.stages.len(), 1);
+        assert_eq!(template.stage_latency(0), 6.0);
+        assert!(generator
+            .get_heterogeneous_pipeline_template(&[2, 0])
+            .is_err());
+        assert!(generator.get_pipeline_template(1).is_err());
+    }
+
+    #[test]
+    fn test_heterogeneous_same_devices_as_homogeneous() {
+        let config = PlannerConfig {
+            device_classes: vec![DeviceClass::default(), DeviceClass::default()],
+            ..Default::default()
+        };
+        let mut heterogeneous = PipelineTemplateGenerator::new(uniform_layers(6, 0), config);
+        heterogeneous
+            .divide_and_conquer_heterogeneous(&[2, 1])
+            .unwrap();
+        let homogeneous = prepare(6, true, vec![3]).unwrap();
+
+        for (num_nodes, num_stages) in [(vec![1, 0], 1), (vec![1, 1], 2), (vec![2, 1], 3)] {
+            assert_eq!(
+                heterogeneous
+                    .get_heterogeneous_pipeline_template(&num_nodes)
+                    .unwrap()
+                    .latency(),
+                homogeneous
+                    .get_pipeline_template(num_stages)
+                    .unwrap()
+                    .latency()
+            );
+        }
+    }
+
+    #[test]
+    fn test_heterogeneous_memory_per_device_class() {
+        let mut layer_results = uniform_layers(6, 0);
+        for layer in layer_results.iter_mut() {
+            layer.param_mem = 10;
+        }
+        // The faster device has too little memory to take more than two layers
+        let mut h100 = DeviceClass::new("h100", 2.0);
+        h100.memory_budget = Some(MemoryBudget::new(20, 0.0).unwrap());
+        let config = PlannerConfig {
+            device_classes: vec![DeviceClass::new("a100", 1.0), h100],
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer_heterogeneous(&[1, 1]).unwrap();
+
+        let template = generator
+            .get_heterogeneous_pipeline_template(&[1, 1])
+            .unwrap();
+        for (stage, memory) in template.stages.iter().zip(template.stage_memory.iter()) {
+            if stage.device_class == 1 {
+                assert_eq!(stage.layers.1 - stage.layers.0, 2);
+                assert_eq!(*memory, 20);
+            }
+        }
+
+        let error = generator
+            .get_heterogeneous_pipeline_template(&[0, 1])
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            PlannerError::MemoryExceeded { num_nodes, .. } if num_nodes == vec![0, 1]
+        ));
+    }
+
+    #[test]
+    fn test_heterogeneous_profile_per_device_class() {
+        let mut profile = uniform_layers(4, 0);
+        profile[3].forward = 7.0;
+        let mut h100 = DeviceClass::new("h100", 1.0);
+        h100.profile = Some(profile);
+        let config = PlannerConfig {
+            device_classes: vec![DeviceClass::new("a100", 1.0), h100],
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(uniform_layers(4, 0), config);
+        generator.divide_and_conquer_heterogeneous(&[0, 1]).unwrap();
+
+        let template = generator
+            .get_heterogeneous_pipeline_template(&[0, 1])
+            .unwrap();
+        assert_eq!(template.stage_latency(0), 14.0);
+
+        let config = PlannerConfig {
+            device_classes: vec![DeviceClass {
+                profile: Some(uniform_layers(3, 0)),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(uniform_layers(4, 0), config);
+        assert!(generator.divide_and_conquer(1).is_err());
+    }
+
+    #[test]
+    fn test_multiple_stages_per_node() {
+        let mut layer_results = uniform_layers(8, 0);
+        // Sending the output of layer 3 across nodes takes 10ms over a 1GB/s link
+        layer_results[3].activation_size = 10_000_000;
+
+        let config = PlannerConfig {
+            link: LinkSpec::new(1.0, 0.5),
+            gpus_per_node: 2,
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(2).unwrap();
+
+        let template = generator.get_pipeline_template(2).unwrap();
+        assert_eq!(template.stages.len(), 4);
+        assert_eq!(template.stages_per_node(2), vec![vec![0, 1], vec![2, 3]]);
+        // Only the boundary between nodes pays for the link
+        assert_eq!(template.comm, vec![0.0, 1.0, 1.0, 0.0]);
+        assert_ne!(template.stages[2].layers.0, 4);
+
+        // A single node holds two stages
+        let template = generator.get_pipeline_template(1).unwrap();
+        assert_eq!(template.stages.len(), 2);
+        assert!(generator.get_pipeline_template(3).is_err());
+
+        let mut generator = PipelineTemplateGenerator::new(
+            uniform_layers(8, 0),
+            PlannerConfig {
+                gpus_per_node: 4,
+                ..Default::default()
+            },
+        );
+        assert!(generator.divide_and_conquer(3).is_err());
+    }
+
+    #[test]
+    fn test_noisy_stage_avoided_at_high_percentile() {
+        let mut layer_results = uniform_layers(4, 0);
+        layer_results[0].forward_std = 2.0;
+
+        let mut generator =
+            PipelineTemplateGenerator::new(layer_results.clone(), PlannerConfig::default());
+        generator.divide_and_conquer(2).unwrap();
+        let template = generator.get_pipeline_template(2).unwrap();
+        assert_eq!(template.stages[0].layers, (0, 2));
+
+        // At p99 the first layer takes 2 + 2.3263 * 2ms, so it is left alone in its stage
+        let config = PlannerConfig {
+            latency_statistic: LatencyStatistic::P99,
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results, config);
+        generator.divide_and_conquer(2).unwrap();
+        let template = generator.get_pipeline_template(2).unwrap();
+        assert_eq!(template.stages[0].layers, (0, 1));
+        assert!((template.stage_latency(0) - 6.6526).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_latency_variance_from_samples() {
+        let mut layer = LayerExecutionResult {
+            samples: vec![1.0, 2.0, 3.0],
+            ..Default::default()
+        };
+        assert_eq!(layer.latency_variance(), 1.0);
+
+        // A given standard deviation takes precedence over samples
+        layer.forward_std = 1.0;
+        layer.backward_std = 2.0;
+        assert_eq!(layer.latency_variance(), 5.0);
+    }
+
+    #[test]
+    fn test_pareto_frontier_of_latency_and_memory() {
+        let mut layer_results = uniform_layers(4, 0);
+        for layer in layer_results.iter_mut() {
+            layer.param_mem = 1;
+        }
+        layer_results[0].param_mem = 40;
+
+        let config = PlannerConfig {
+            search: SearchMode::ParetoFrontier,
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(layer_results.clone(), config);
+        generator.divide_and_conquer(2).unwrap();
+
+        // The even split is the fastest, and moving layer 1 off the first stage saves memory.
+        // Moving layer 3 onto the first stage is slower and needs more memory.
+        let templates = generator.get_pipeline_templates(2).unwrap();
+        assert_eq!(templates.len(), 2);
+        assert_eq!(templates[0].stages[0].layers, (0, 2));
+        assert_eq!(templates[0].peak_memory(), 41);
+        assert_eq!(templates[1].stages[0].layers, (0, 1));
+        assert_eq!(templates[1].peak_memory(), 40);
+        assert!(templates[0].latency() < templates[1].latency());
+        assert_eq!(
+            generator.get_pipeline_template(2).unwrap().stages[0].layers,
+            (0, 2)
+        );
+
+        let mut generator = PipelineTemplateGenerator::new(layer_results, PlannerConfig::default());
+        generator.divide_and_conquer(2).unwrap();
+        assert_eq!(generator.get_pipeline_templates(2).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_pareto_frontier_drops_equally_fast_candidates_with_more_memory() {
+        let candidate = |latency: f64, mem_required: u64, peak_memory: u64| Candidate {
+            rest: LatencySummary::default(),
+            head: latency,
+            latency,
+            mem_required,
+            peak_memory,
+            end: 0,
+            device_class: 0,
+            checkpointed: false,
+            next: 0,
+        };
+        let config = PlannerConfig {
+            search: SearchMode::ParetoFrontier,
+            ..Default::default()
+        };
+        let generator = PipelineTemplateGenerator::new(uniform_layers(2, 0), config);
+
+        // The second candidate is as fast as the first with a lower peak
+        let kept = generator.prune(vec![
+            candidate(1.0, 10, 8),
+            candidate(1.0, 12, 6),
+            candidate(2.0, 4, 4),
+        ]);
+        let peaks: Vec<u64> = kept.iter().map(|c| c.peak_memory).collect();
+        assert_eq!(peaks, vec![6, 4]);
+    }
+
+    #[test]
+    fn test_top_k_distinct_templates() {
+        let config = PlannerConfig {
+            search: SearchMode::TopK(3),
+            ..Default::default()
+        };
+        let mut generator = PipelineTemplateGenerator::new(uniform_layers(6, 0), config);
+        generator.divide_and_conquer(3).unwrap();
+
+        let templates = generator.get_pipeline_templates(3).unwrap();
+        assert_eq!(templates.len(), 3);
+        assert_eq!(
+            templates[0]
+                .stages
+                .iter()
+                .map(|stage| stage.layers)
+                .collect::<Vec<(u32, u32)>>(),
+            vec![(0, 2), (2, 4), (4, 6)]
+        );
+        let layout = |template: &PipelineExecutionResult| {
+            template
+                .stages
+                .iter()
+                .map(|stage| (stage.layers, stage.device_class, stage.checkpointed))
+                .collect::<Vec<((u32, u32), usize, bool)>>()
+        };
+        for k in 1..templates.len() {
+            assert!(templates[k - 1].latency() <= templates[k].latency());
+            for l in 0..k {
+                assert_ne!(layout(&templates[k]), layout(&templates[l]));
+            }
+        }
+
+        // Only one way to put one stage per layer
+        assert_eq!(generator.get_pipeline_templates(1).unwrap().len(), 1);
+    }
+
     #[test]
     fn test_measure_time_of_large_model() {
         let generator = prepare(96, false, vec![64]).unwrap();
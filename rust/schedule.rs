@@ -0,0 +1,296 @@
+use crate::PlannerError;
+use std::sync::Arc;
+
+/// Cost model of a pipeline schedule.
+///
+/// Stage latencies passed to a schedule are per microbatch and include both forward and
+/// backward passes as well as p2p communication.
+pub trait PipelineSchedule: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Splits the iteration time into (t1, t2, t3): warmup, steady state and cooldown.
+    fn iteration_time(&self, latencies: &LatencySummary, num_microbatches: f64) -> (f64, f64, f64);
+
+    /// Number of microbatches whose activations the given stage keeps at its peak.
+    fn in_flight_microbatches(
+        &self,
+        stage_index: usize,
+        num_stages: usize,
+        num_microbatches: u32,
+    ) -> f64;
+}
+
+/// Stage latencies of a pipeline as far as schedules are concerned: the slowest stage
+/// and the total latency of the stages before and after it.
+///
+/// A stage can be put in front of a pipeline without knowing its other stages.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LatencySummary {
+    pub num_stages: usize,
+    // Index and latency of the slowest stage. Ties are broken towards the later stage.
+    pub kstar: usize,
+    pub slowest: f64,
+    // Time for the first microbatch to reach the slowest stage and for the last
+    // microbatch to leave it, i.e. the bubble of a schedule that keeps it busy
+    pub fill: f64,
+    pub drain: f64,
+}
+
+impl LatencySummary {
+    pub fn new(stage_latencies: &[f64], kstar: usize) -> Self {
+        LatencySummary {
+            num_stages: stage_latencies.len(),
+            kstar,
+            slowest: stage_latencies[kstar],
+            fill: stage_latencies[..kstar].iter().sum(),
+            drain: stage_latencies[kstar + 1..].iter().sum(),
+        }
+    }
+
+    /// The same pipeline with a stage of the given latency put in front.
+    pub fn prepend(&self, latency: f64) -> Self {
+        if self.num_stages == 0 || latency > self.slowest {
+            LatencySummary {
+                num_stages: self.num_stages + 1,
+                kstar: 0,
+                slowest: latency,
+                fill: 0.0,
+                drain: self.total(),
+            }
+        } else {
+            LatencySummary {
+                num_stages: self.num_stages + 1,
+                kstar: self.kstar + 1,
+                fill: self.fill + latency,
+                ..*self
+            }
+        }
+    }
+
+    /// Sum of all stage latencies.
+    pub fn total(&self) -> f64 {
+        self.fill + self.slowest + self.drain
+    }
+}
+
+/// All forward passes first, then all backward passes.
+pub struct GPipe;
+
+impl PipelineSchedule for GPipe {
+    fn name(&self) -> &'static str {
+        "gpipe"
+    }
+
+    fn iteration_time(&self, latencies: &LatencySummary, num_microbatches: f64) -> (f64, f64, f64) {
+        (
+            latencies.fill,
+            num_microbatches * latencies.slowest,
+            latencies.drain,
+        )
+    }
+
+    fn in_flight_microbatches(&self, _: usize, _: usize, num_microbatches: u32) -> f64 {
+        num_microbatches as f64
+    }
+}
+
+/// One forward one backward (PipeDream-Flush), the schedule Colossal-AI runs by default.
+pub struct OneForwardOneBackward;
+
+impl PipelineSchedule for OneForwardOneBackward {
+    fn name(&self) -> &'static str {
+        "1f1b"
+    }
+
+    /// Once the first microbatch reaches the slowest stage, that stage runs the forward and
+    /// backward passes of every microbatch back to back. The bubble is the same as GPipe's;
+    /// 1F1B keeps fewer activations in flight.
+    fn iteration_time(&self, latencies: &LatencySummary, num_microbatches: f64) -> (f64, f64, f64) {
+        (
+            latencies.fill,
+            num_microbatches * latencies.slowest,
+            latencies.drain,
+        )
+    }
+
+    fn in_flight_microbatches(
+        &self,
+        stage_index: usize,
+        num_stages: usize,
+        num_microbatches: u32,
+    ) -> f64 {
+        (num_stages - stage_index).min(num_microbatches as usize) as f64
+    }
+}
+
+/// Interleaved 1F1B, where each stage holds `num_chunks` model chunks.
+///
+/// Chunks are not modeled as separate stages; the bubble of the contiguous split is
+/// shrunk by the number of chunks instead.
+pub struct Interleaved1F1B {
+    pub num_chunks: u32,
+}
+
+impl PipelineSchedule for Interleaved1F1B {
+    fn name(&self) -> &'static str {
+        "interleaved"
+    }
+
+    fn iteration_time(&self, latencies: &LatencySummary, num_microbatches: f64) -> (f64, f64, f64) {
+        let num_chunks = self.num_chunks as f64;
+        (
+            latencies.fill / num_chunks,
+            num_microbatches * latencies.slowest,
+            latencies.drain / num_chunks,
+        )
+    }
+
+    fn in_flight_microbatches(
+        &self,
+        stage_index: usize,
+        num_stages: usize,
+        num_microbatches: u32,
+    ) -> f64 {
+        // Warmup microbatches of Megatron-LM's interleaved schedule, counted in chunks.
+        let num_chunks = self.num_chunks as usize;
+        let num_warmup = ((num_stages - stage_index - 1) * 2 + (num_chunks - 1) * num_stages)
+            .min(num_microbatches as usize * num_chunks);
+        (num_warmup + 1) as f64 / num_chunks as f64
+    }
+}
+
+/// ZB-H1 zero bubble schedule, which defers weight gradient computation to fill bubbles.
+///
+/// Assumes a backward pass takes twice as long as a forward pass and is split evenly
+/// into input and weight gradient computation, leaving a third of the 1F1B bubble.
+pub struct ZeroBubbleH1;
+
+impl PipelineSchedule for ZeroBubbleH1 {
+    fn name(&self) -> &'static str {
+        "zbh1"
+    }
+
+    fn iteration_time(&self, latencies: &LatencySummary, num_microbatches: f64) -> (f64, f64, f64) {
+        (
+            latencies.fill / 3.0,
+            num_microbatches * latencies.slowest,
+            latencies.drain / 3.0,
+        )
+    }
+
+    fn in_flight_microbatches(
+        &self,
+        stage_index: usize,
+        num_stages: usize,
+        num_microbatches: u32,
+    ) -> f64 {
+        // Same peak activation memory as 1F1B
+        OneForwardOneBackward.in_flight_microbatches(stage_index, num_stages, num_microbatches)
+    }
+}
+
+/// Returns the schedule with the given name.
+pub fn get_schedule(
+    name: &str,
+    num_model_chunks: u32,
+) -> Result<Arc<dyn PipelineSchedule>, PlannerError> {
+    match name {
+        "gpipe" => Ok(Arc::new(GPipe)),
+        "1f1b" => Ok(Arc::new(OneForwardOneBackward)),
+        "interleaved" => {
+            if num_model_chunks == 0 {
+                return Err(PlannerError::invalid_config(
+                    "Interleaved schedule requires at least one model chunk",
+                ));
+            }
+            Ok(Arc::new(Interleaved1F1B {
+                num_chunks: num_model_chunks,
+            }))
+        }
+        "zbh1" => Ok(Arc::new(ZeroBubbleH1)),
+        _ => Err(PlannerError::invalid_config(format!(
+            "Unknown pipeline schedule: {}",
+            name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn total(schedule: &dyn PipelineSchedule, latencies: &[f64], kstar: usize, mb: f64) -> f64 {
+        let (t1, t2, t3) = schedule.iteration_time(&LatencySummary::new(latencies, kstar), mb);
+        t1 + t2 + t3
+    }
+
+    #[test]
+    fn test_uniform_stages() {
+        // 4 stages of 2ms with 8 microbatches: (8 + 4 - 1) * 2 without interleaving
+        let latencies = vec![2.0; 4];
+        assert_eq!(total(&GPipe, &latencies, 3, 8.0), 22.0);
+        assert_eq!(total(&OneForwardOneBackward, &latencies, 3, 8.0), 22.0);
+        assert_eq!(
+            total(&Interleaved1F1B { num_chunks: 2 }, &latencies, 3, 8.0),
+            19.0
+        );
+        assert_eq!(total(&ZeroBubbleH1, &latencies, 3, 8.0), 18.0);
+    }
+
+    #[test]
+    fn test_slowest_stage_bounds_iteration() {
+        // Stage 0 alone runs 2 microbatches of 3ms, and the last one still has to pass stage 1
+        let latencies = vec![3.0, 1.0];
+        assert_eq!(total(&OneForwardOneBackward, &latencies, 0, 2.0), 7.0);
+        let latencies = vec![1.0, 4.0, 2.0];
+        assert_eq!(total(&OneForwardOneBackward, &latencies, 1, 3.0), 15.0);
+    }
+
+    #[test]
+    fn test_interleaved_with_one_chunk_is_gpipe() {
+        let latencies = vec![1.0, 3.0, 2.0];
+        assert_eq!(
+            total(&Interleaved1F1B { num_chunks: 1 }, &latencies, 1, 6.0),
+            total(&GPipe, &latencies, 1, 6.0)
+        );
+    }
+
+    #[test]
+    fn test_prepend_stages() {
+        let latencies = vec![1.0, 3.0, 3.0, 2.0];
+        let summary = latencies
+            .iter()
+            .rev()
+            .fold(LatencySummary::default(), |summary, &latency| {
+                summary.prepend(latency)
+            });
+        assert_eq!(summary, LatencySummary::new(&latencies, 2));
+        assert_eq!(summary.total(), 9.0);
+        assert_eq!(summary.prepend(4.0).kstar, 0);
+    }
+
+    #[test]
+    fn test_in_flight_microbatches() {
+        assert_eq!(GPipe.in_flight_microbatches(0, 4, 16), 16.0);
+        assert_eq!(OneForwardOneBackward.in_flight_microbatches(0, 4, 16), 4.0);
+        assert_eq!(OneForwardOneBackward.in_flight_microbatches(3, 4, 16), 1.0);
+        assert_eq!(OneForwardOneBackward.in_flight_microbatches(0, 4, 2), 2.0);
+        assert_eq!(ZeroBubbleH1.in_flight_microbatches(1, 4, 16), 3.0);
+
+        // First stage keeps more activations than 1F1B, though of smaller chunks
+        let interleaved = Interleaved1F1B { num_chunks: 2 };
+        assert_eq!(interleaved.in_flight_microbatches(0, 4, 16), 5.5);
+        assert_eq!(interleaved.in_flight_microbatches(3, 4, 16), 2.5);
+    }
+
+    #[test]
+    fn test_get_schedule() {
+        assert_eq!(get_schedule("1f1b", 1).unwrap().name(), "1f1b");
+        assert_eq!(
+            get_schedule("interleaved", 2).unwrap().name(),
+            "interleaved"
+        );
+        assert!(get_schedule("interleaved", 0).is_err());
+        assert!(get_schedule("pipedream", 1).is_err());
+    }
+}
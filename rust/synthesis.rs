@@ -0,0 +1,414 @@
+use crate::execution_result::{LayerExecutionResult, ProfileResult};
+use crate::memory_model::{MemoryModel, Precision};
+use crate::validation::ProfileError;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Shape of a decoder-only transformer, as given by a HuggingFace `config.json`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformerConfig {
+    // e.g. transformers.GPT2LMHeadModel, the name ModelProfiler profiles under
+    pub model_name: String,
+    pub model_type: String,
+    pub hidden_size: u64,
+    pub num_layers: u64,
+    pub num_heads: u64,
+    pub num_kv_heads: u64,
+    pub intermediate_size: u64,
+    pub vocab_size: u64,
+    // Learned position embeddings, zero for rotary embeddings
+    pub num_positions: u64,
+    pub seq_length: u64,
+    // Gated MLPs (e.g. SwiGLU) have three weight matrices instead of two
+    pub gated_mlp: bool,
+    // The output head shares its weight with the token embedding, as in GPT-2
+    pub tie_word_embeddings: bool,
+}
+
+/// First of the keys the config has, as different model families name the same field differently.
+fn get_u64(config: &Value, keys: &[&str]) -> Option<u64> {
+    keys.iter().find_map(|key| config[*key].as_u64())
+}
+
+fn required(config: &Value, field: &'static str, keys: &[&str]) -> Result<u64, ProfileError> {
+    get_u64(config, keys).ok_or_else(|| ProfileError::InvalidMetadata {
+        field,
+        reason: "missing from the model config".to_string(),
+    })
+}
+
+impl TransformerConfig {
+    /// Reads a HuggingFace config. `seq_length` defaults to the longest sequence the model takes.
+    pub fn from_hf_config(config: &Value, seq_length: Option<u64>) -> Result<Self, ProfileError> {
+        let model_type = config["model_type"].as_str().unwrap_or("").to_string();
+        let hidden_size = required(config, "hidden_size", &["hidden_size", "n_embd", "d_model"])?;
+        let num_heads = required(
+            config,
+            "num_attention_heads",
+            &["num_attention_heads", "n_head"],
+        )?;
+        let max_positions = get_u64(config, &["max_position_embeddings", "n_positions", "n_ctx"]);
+        let seq_length = match seq_length.or(max_positions) {
+            Some(seq_length) if seq_length > 0 => seq_length,
+            _ => {
+                return Err(ProfileError::InvalidMetadata {
+                    field: "seq_length",
+                    reason: "not given and missing from the model config".to_string(),
+                })
+            }
+        };
+        let hidden_act = config["hidden_act"]
+            .as_str()
+            .or(config["activation_function"].as_str())
+            .unwrap_or("");
+
+        let transformer = TransformerConfig {
+            model_name: match config["architectures"]
+                .as_array()
+                .and_then(|architectures| architectures.first())
+                .and_then(Value::as_str)
+            {
+                Some(architecture) => format!("transformers.{}", architecture),
+                None => model_type.clone(),
+            },
+            hidden_size,
+            num_layers: required(
+                config,
+                "num_hidden_layers",
+                &["num_hidden_layers", "n_layer", "num_layers"],
+            )?,
+            num_heads,
+            num_kv_heads: get_u64(config, &["num_key_value_heads"]).unwrap_or(num_heads),
+            intermediate_size: get_u64(config, &["intermediate_size", "n_inner", "ffn_dim"])
+                .unwrap_or(4 * hidden_size),
+            vocab_size: required(config, "vocab_size", &["vocab_size"])?,
+            num_positions: if model_type == "gpt2" {
+                max_positions.unwrap_or(0)
+            } else {
+                0
+            },
+            seq_length,
+            gated_mlp: matches!(hidden_act, "silu" | "swiglu"),
+            // HuggingFace ties them unless the config says otherwise
+            tie_word_embeddings: config["tie_word_embeddings"].as_bool().unwrap_or(true),
+            model_type,
+        };
+        if transformer.hidden_size == 0
+            || transformer.num_heads == 0
+            || !transformer
+                .hidden_size
+                .is_multiple_of(transformer.num_heads)
+        {
+            return Err(ProfileError::InvalidMetadata {
+                field: "hidden_size",
+                reason: format!(
+                    "{} is not divisible into {} heads",
+                    transformer.hidden_size, transformer.num_heads
+                ),
+            });
+        }
+        Ok(transformer)
+    }
+
+    /// Reads `config.json`, or the one in the given directory.
+    pub fn load(path: &Path, seq_length: Option<u64>) -> Result<Self, ProfileError> {
+        let path = if path.is_dir() {
+            path.join("config.json")
+        } else {
+            path.to_path_buf()
+        };
+        let unreadable = |reason: String| ProfileError::UnreadableFile {
+            path: path.display().to_string(),
+            reason,
+        };
+        let data = fs::read_to_string(&path).map_err(|e| unreadable(e.to_string()))?;
+        let config: Value = serde_json::from_str(&data).map_err(|e| unreadable(e.to_string()))?;
+        Self::from_hf_config(&config, seq_length)
+    }
+
+    /// Module names of the embedding, transformer blocks, final norm and output head.
+    fn layer_names(&self) -> Vec<String> {
+        let (prefix, embedding, blocks, norm) = match self.model_type.as_str() {
+            "gpt2" => ("transformer", "wte", "h", "ln_f"),
+            _ => ("model", "embed_tokens", "layers", "norm"),
+        };
+        let mut names = vec![format!("{}.{}", prefix, embedding)];
+        names.extend((0..self.num_layers).map(|i| format!("{}.{}.{}", prefix, blocks, i)));
+        names.push(format!("{}.{}", prefix, norm));
+        names.push("lm_head".to_string());
+        names
+    }
+}
+
+/// Roofline of a device: a layer takes as long as its FLOPs or its memory traffic needs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeviceSpec {
+    // FLOP/s
+    pub peak_flops: f64,
+    // Bytes/s
+    pub memory_bandwidth: f64,
+}
+
+impl DeviceSpec {
+    /// Milliseconds to run the given work.
+    fn time(&self, flops: f64, bytes: f64) -> f64 {
+        (flops / self.peak_flops).max(bytes / self.memory_bandwidth) * 1000.0
+    }
+}
+
+// Forward pass work and memory of a layer on one tensor parallel rank
+#[derive(Clone, Copy)]
+struct LayerCost {
+    num_parameters: u64,
+    // Parameters the layer reads but another layer holds, i.e. tied embeddings
+    shared_parameters: u64,
+    flops: f64,
+    // Activations stashed for backward and the output sent to the next layer (bytes)
+    activation_mem: u64,
+    activation_size: u64,
+}
+
+/// Profile of a transformer predicted from its shape and a device roofline.
+///
+/// The backward pass does twice the work of the forward pass. Activation memory follows
+/// Korthikanti et al., "Reducing Activation Recomputation in Large Transformer Models".
+/// Parameters and work of every layer but the final norm are split evenly across `tp_size` ranks.
+/// A head tied to the embedding is counted with the embedding.
+pub fn synthesize_profile(
+    config: &TransformerConfig,
+    device: &DeviceSpec,
+    microbatch_size: u32,
+    tp_size: u32,
+    memory_model: &MemoryModel,
+) -> Result<ProfileResult, ProfileError> {
+    if !(device.peak_flops > 0.0 && device.memory_bandwidth > 0.0) {
+        return Err(ProfileError::InvalidMetadata {
+            field: "device",
+            reason: "peak FLOPs and memory bandwidth must be positive".to_string(),
+        });
+    }
+    if microbatch_size == 0 || tp_size == 0 {
+        return Err(ProfileError::InvalidMetadata {
+            field: "microbatch_size",
+            reason: "microbatch and tensor parallel sizes must be positive".to_string(),
+        });
+    }
+
+    let bytes_per_parameter = memory_model.bytes_per_parameter();
+    // Activations are kept in 16 bits with fp8 weights
+    let activation_bytes = bytes_per_parameter.weight.max(2);
+    let (b, s, h) = (
+        microbatch_size as u64,
+        config.seq_length,
+        config.hidden_size,
+    );
+    let t = tp_size as u64;
+    let hidden_states = b * s * h * activation_bytes;
+
+    let kv_size = h * config.num_kv_heads / config.num_heads;
+    let attention_parameters = 2 * h * h + 2 * h * kv_size;
+    let mlp_parameters = if config.gated_mlp { 3 } else { 2 } * h * config.intermediate_size;
+    let block = LayerCost {
+        num_parameters: (attention_parameters + mlp_parameters) / t + 4 * h,
+        shared_parameters: 0,
+        flops: (2 * b * s * (attention_parameters + mlp_parameters) + 4 * b * s * s * h) as f64
+            / t as f64,
+        activation_mem: b * s * (34 * h + 5 * config.num_heads * s) * activation_bytes / 2 / t,
+        activation_size: hidden_states,
+    };
+    let embedding = LayerCost {
+        num_parameters: (config.vocab_size + config.num_positions) * h / t,
+        shared_parameters: 0,
+        flops: 0.0,
+        activation_mem: hidden_states,
+        activation_size: hidden_states,
+    };
+    let norm = LayerCost {
+        num_parameters: 2 * h,
+        shared_parameters: 0,
+        flops: (5 * b * s * h) as f64,
+        activation_mem: hidden_states,
+        activation_size: hidden_states,
+    };
+    let head_parameters = config.vocab_size * h / t;
+    let (num_parameters, shared_parameters) = if config.tie_word_embeddings {
+        (0, head_parameters)
+    } else {
+        (head_parameters, 0)
+    };
+    let head = LayerCost {
+        num_parameters,
+        shared_parameters,
+        flops: (2 * b * s * h * config.vocab_size) as f64 / t as f64,
+        activation_mem: b * s * config.vocab_size * activation_bytes / t,
+        activation_size: 0,
+    };
+
+    let mut costs = vec![embedding];
+    costs.extend((0..config.num_layers).map(|_| block));
+    costs.push(norm);
+    costs.push(head);
+
+    let layers = config
+        .layer_names()
+        .into_iter()
+        .zip(costs.iter())
+        .enumerate()
+        .map(|(index, (layer_name, cost))| {
+            let param_mem = cost.num_parameters * bytes_per_parameter.total();
+            let traffic = ((cost.num_parameters + cost.shared_parameters)
+                * bytes_per_parameter.weight
+                + cost.activation_mem) as f64;
+            let forward = device.time(cost.flops, traffic);
+            LayerExecutionResult {
+                layer_index: index as u32,
+                layer_name,
+                forward,
+                backward: device.time(2.0 * cost.flops, 2.0 * traffic),
+                mem_required: param_mem + cost.activation_mem,
+                activation_size: cost.activation_size,
+                param_mem,
+                activation_mem: cost.activation_mem,
+                num_parameters: cost.num_parameters,
+                recompute: forward,
+                // Checkpointing keeps only the input of the layer
+                checkpoint_mem_saved: cost.activation_mem.saturating_sub(hidden_states),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    Ok(ProfileResult::new(
+        config.model_name.clone(),
+        microbatch_size,
+        tp_size,
+        match memory_model.precision {
+            Precision::Fp32 => "fp32",
+            Precision::Fp16 => "fp16",
+            Precision::Bf16 => "bf16",
+            Precision::Fp8 => "fp8",
+        }
+        .to_string(),
+        layers,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_model::Optimizer;
+    use crate::validation::validate_profile;
+
+    fn gpt2() -> Value {
+        serde_json::from_str(
+            r#"{
+                "architectures": ["GPT2LMHeadModel"],
+                "model_type": "gpt2",
+                "n_embd": 768,
+                "n_head": 12,
+                "n_layer": 12,
+                "n_positions": 1024,
+                "vocab_size": 50257,
+                "activation_function": "gelu_new"
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn a100() -> DeviceSpec {
+        DeviceSpec {
+            peak_flops: 312e12,
+            memory_bandwidth: 2.0e12,
+        }
+    }
+
+    #[test]
+    fn test_read_hf_config() {
+        let config = TransformerConfig::from_hf_config(&gpt2(), None).unwrap();
+        assert_eq!(config.model_name, "transformers.GPT2LMHeadModel");
+        assert_eq!(config.hidden_size, 768);
+        assert_eq!(config.intermediate_size, 3072);
+        assert_eq!(config.num_kv_heads, 12);
+        assert_eq!(config.seq_length, 1024);
+        assert!(!config.gated_mlp);
+        assert_eq!(config.layer_names()[1], "transformer.h.0");
+
+        let llama: Value = serde_json::from_str(
+            r#"{
+                "model_type": "llama",
+                "hidden_size": 4096,
+                "num_attention_heads": 32,
+                "num_key_value_heads": 8,
+                "num_hidden_layers": 32,
+                "intermediate_size": 14336,
+                "vocab_size": 128256,
+                "hidden_act": "silu"
+            }"#,
+        )
+        .unwrap();
+        assert!(TransformerConfig::from_hf_config(&llama, None).is_err());
+        let config = TransformerConfig::from_hf_config(&llama, Some(2048)).unwrap();
+        assert!(config.gated_mlp);
+        assert_eq!(config.num_positions, 0);
+        assert_eq!(config.layer_names()[33], "model.norm");
+    }
+
+    #[test]
+    fn test_synthesize_profile() {
+        let config = TransformerConfig::from_hf_config(&gpt2(), Some(512)).unwrap();
+        let memory_model = MemoryModel::new(Precision::Bf16, Optimizer::AdamW);
+        let profile = synthesize_profile(&config, &a100(), 2, 1, &memory_model).unwrap();
+        assert_eq!(profile.layers.len(), 15);
+        assert_eq!(profile.precision, "bf16");
+        validate_profile(&profile.layers).unwrap();
+
+        // About 7M parameters per block, 16 bytes each
+        let block = &profile.layers[1];
+        assert_eq!(block.num_parameters, 12 * 768 * 768 + 4 * 768);
+        assert_eq!(block.param_mem, 16 * block.num_parameters);
+        assert!(block.backward > block.forward);
+        assert!(profile.layers[14].forward > block.forward);
+
+        // The head of GPT-2 shares the embedding weight
+        assert!(config.tie_word_embeddings);
+        assert_eq!(profile.layers[14].num_parameters, 0);
+        assert_eq!(profile.layers[0].num_parameters, (50257 + 1024) * 768);
+        let untied = TransformerConfig {
+            tie_word_embeddings: false,
+            ..config.clone()
+        };
+        let untied = synthesize_profile(&untied, &a100(), 2, 1, &memory_model).unwrap();
+        assert_eq!(untied.layers[14].num_parameters, 50257 * 768);
+        assert_eq!(untied.layers[14].forward, profile.layers[14].forward);
+
+        // Tensor parallelism halves the work and memory of a block
+        let sharded = synthesize_profile(&config, &a100(), 2, 2, &memory_model).unwrap();
+        assert!(sharded.layers[1].forward < block.forward);
+        assert!(sharded.layers[1].activation_mem * 2 == block.activation_mem);
+    }
+
+    #[test]
+    fn test_return_error_for_invalid_input() {
+        let config = TransformerConfig::from_hf_config(&gpt2(), None).unwrap();
+        let memory_model = MemoryModel::new(Precision::Fp32, Optimizer::Adam);
+        let device = DeviceSpec {
+            peak_flops: 0.0,
+            memory_bandwidth: 1.0,
+        };
+        assert!(synthesize_profile(&config, &device, 1, 1, &memory_model).is_err());
+        assert!(synthesize_profile(&config, &a100(), 0, 1, &memory_model).is_err());
+
+        let mut incomplete = gpt2();
+        if let Value::Object(fields) = &mut incomplete {
+            fields.remove("vocab_size");
+        }
+        assert!(matches!(
+            TransformerConfig::from_hf_config(&incomplete, None),
+            Err(ProfileError::InvalidMetadata {
+                field: "vocab_size",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::config::PlannerConfig;
+use crate::execution_result::{LayerExecutionResult, PipelineExecutionResult};
+use crate::pipeline_template_generator::PipelineTemplateGenerator;
+use crate::PlannerError;
+
+/// Plans pipelines for several tensor parallel degrees and picks the fastest
+/// TP x PP combination for each number of GPUs.
+///
+/// Every stage of a pipeline planned with tensor parallel degree `tp` runs on `tp` GPUs,
+/// so `num_gpus` GPUs make a pipeline of `num_gpus / tp` stages. A node hosts
+/// `gpus_per_node / tp` stages, or a single stage if its tensor parallel group spans nodes.
+pub struct TensorParallelPlanner {
+    // Key: tensor parallel degree the profile was taken with, sorted
+    generators: Vec<(u32, PipelineTemplateGenerator)>,
+}
+
+impl TensorParallelPlanner {
+    pub fn new(
+        mut profiles: Vec<(u32, Vec<LayerExecutionResult>)>,
+        config: PlannerConfig,
+    ) -> Result<Self, PlannerError> {
+        if profiles.is_empty() {
+            return Err(PlannerError::invalid_config("No profile is given"));
+        }
+        profiles.sort_by_key(|(tp_size, _)| *tp_size);
+        for k in 0..profiles.len() {
+            if profiles[k].0 == 0 || (k > 0 && profiles[k].0 == profiles[k - 1].0) {
+                return Err(PlannerError::invalid_config(format!(
+                    "Invalid or duplicate tensor parallel degree: {}",
+                    profiles[k].0
+                )));
+            }
+            // Tensor parallel groups either share nodes evenly or span whole nodes
+            let tp_size = profiles[k].0;
+            if !(config.gpus_per_node.is_multiple_of(tp_size)
+                || tp_size.is_multiple_of(config.gpus_per_node))
+            {
+                return Err(PlannerError::invalid_config(format!(
+                    "Tensor parallel degree {} neither divides nor is a multiple of {} GPUs per node",
+                    tp_size, config.gpus_per_node
+                )));
+            }
+        }
+
+        Ok(TensorParallelPlanner {
+            generators: profiles
+                .into_iter()
+                .map(|(tp_size, profile)| {
+                    let config = PlannerConfig {
+                        gpus_per_node: (config.gpus_per_node / tp_size).max(1),
+                        ..config.clone()
+                    };
+                    (tp_size, PipelineTemplateGenerator::new(profile, config))
+                })
+                .collect(),
+        })
+    }
+
+    pub fn divide_and_conquer(&mut self, max_num_gpus: u32) -> Result<(), PlannerError> {
+        for (tp_size, generator) in self.generators.iter_mut() {
+            // Larger pipelines are infeasible for this degree rather than an error
+            let stages_per_node = generator.config().gpus_per_node;
+            let max_num_nodes = (max_num_gpus / (stages_per_node * *tp_size))
+                .min(generator.layer_execution_results.len() as u32 / stages_per_node);
+            if max_num_nodes > 0 {
+                generator.divide_and_conquer(max_num_nodes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Generator of the given tensor parallel degree.
+    pub fn generator(&self, tp_size: u32) -> Option<&PipelineTemplateGenerator> {
+        self.generators
+            .iter()
+            .find(|(size, _)| *size == tp_size)
+            .map(|(_, generator)| generator)
+    }
+
+    /// Fastest template over all tensor parallel degrees that divide `num_gpus`,
+    /// along with the chosen degree. If none is feasible, returns the most telling
+    /// error of the degrees tried, whose node counts are those of that degree.
+    pub fn get_pipeline_template(
+        &self,
+        num_gpus: u32,
+    ) -> Result<(u32, PipelineExecutionResult), PlannerError> {
+        let mut best: Option<(u32, PipelineExecutionResult)> = None;
+        let mut error: Option<PlannerError> = None;
+
+        for (tp_size, generator) in self.generators.iter() {
+            let gpus_per_node = generator.config().gpus_per_node * tp_size;
+            if !num_gpus.is_multiple_of(gpus_per_node) {
+                continue;
+            }
+
+            match generator.get_pipeline_template(num_gpus / gpus_per_node) {
+                Ok(result) => {
+                    if best.as_ref().is_none_or(|(_, best)| result < *best) {
+                        best = Some((*tp_size, result));
+                    }
+                }
+                // Running out of memory tells more than having too few layers
+                Err(e) => {
+                    if !matches!(error, Some(PlannerError::MemoryExceeded { .. })) {
+                        error = Some(e);
+                    }
+                }
+            }
+        }
+
+        match (best, error) {
+            (Some(best), _) => Ok(best),
+            (None, Some(error)) => Err(error),
+            (None, None) => Err(PlannerError::invalid_config(format!(
+                "No tensor parallel degree fits {} GPUs",
+                num_gpus
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::MemoryBudget;
+
+    fn layers(num_layers: u32, forward: f64, backward: f64) -> Vec<LayerExecutionResult> {
+        (0..num_layers)
+            .map(|i| LayerExecutionResult {
+                layer_index: i,
+                layer_name: format!("layer{}", i),
+                forward,
+                backward,
+                mem_required: 4,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_choose_faster_tensor_parallel_degree() {
+        // 2ms per layer without tensor parallelism, 0.9ms per layer with two GPUs
+        let profiles = vec![(2, layers(6, 0.5, 0.4)), (1, layers(6, 1.0, 1.0))];
+        let mut planner = TensorParallelPlanner::new(profiles, PlannerConfig::default()).unwrap();
+        planner.divide_and_conquer(4).unwrap();
+
+        // One stage of 5.4ms beats two stages of 6ms
+        let (tp_size, template) = planner.get_pipeline_template(2).unwrap();
+        assert_eq!(tp_size, 2);
+        assert_eq!(template.stages.len(), 1);
+
+        // Only tp=1 divides 3 GPUs
+        let (tp_size, template) = planner.get_pipeline_template(3).unwrap();
+        assert_eq!(tp_size, 1);
+        assert_eq!(template.stages.len(), 3);
+
+        assert!(planner.get_pipeline_template(8).is_err());
+    }
+
+    #[test]
+    fn test_choose_tensor_parallel_degree_that_fits() {
+        // Tensor parallelism reduces the memory per GPU at the cost of latency
+        let mut sharded = layers(4, 1.5, 1.5);
+        for layer in sharded.iter_mut() {
+            layer.mem_required = 1;
+        }
+        let profiles = vec![(1, layers(4, 1.0, 1.0)), (2, sharded)];
+        let config = PlannerConfig {
+            memory_budget: Some(MemoryBudget::new(7, 0.0).unwrap()),
+            ..Default::default()
+        };
+        let mut planner = TensorParallelPlanner::new(profiles, config).unwrap();
+        planner.divide_and_conquer(2).unwrap();
+
+        // Two stages of two layers need 8 bytes each without tensor parallelism
+        let (tp_size, template) = planner.get_pipeline_template(2).unwrap();
+        assert_eq!(tp_size, 2);
+        assert_eq!(template.stages.len(), 1);
+
+        let error = planner.get_pipeline_template(1).err().unwrap();
+        assert!(matches!(
+            error,
+            PlannerError::MemoryExceeded { required: 16, .. }
+        ));
+    }
+
+    #[test]
+    fn test_return_error_for_tensor_parallel_degree_across_nodes() {
+        let config = |gpus_per_node: u32| PlannerConfig {
+            gpus_per_node,
+            ..Default::default()
+        };
+        // Three GPUs of each node would be left idle, or a group would span parts of nodes
+        let profiles = || vec![(1, layers(8, 1.0, 1.0)), (3, layers(8, 0.4, 0.4))];
+        assert!(matches!(
+            TensorParallelPlanner::new(profiles(), config(8)),
+            Err(PlannerError::InvalidConfig(_))
+        ));
+        assert!(TensorParallelPlanner::new(profiles(), config(2)).is_err());
+        assert!(TensorParallelPlanner::new(profiles(), config(3)).is_ok());
+
+        // A group of 4 GPUs spans two nodes of 2
+        let profiles = vec![(1, layers(8, 1.0, 1.0)), (4, layers(8, 0.3, 0.3))];
+        let mut planner = TensorParallelPlanner::new(profiles, config(2)).unwrap();
+        planner.divide_and_conquer(8).unwrap();
+        assert_eq!(planner.generator(4).unwrap().config().gpus_per_node, 1);
+    }
+
+    #[test]
+    fn test_tensor_parallel_groups_within_nodes() {
+        let profiles = vec![(1, layers(8, 1.0, 1.0)), (2, layers(8, 0.6, 0.6))];
+        let config = PlannerConfig {
+            gpus_per_node: 4,
+            ..Default::default()
+        };
+        let mut planner = TensorParallelPlanner::new(profiles, config).unwrap();
+        planner.divide_and_conquer(8).unwrap();
+
+        // Four stages per node without tensor parallelism, two with tp=2
+        assert_eq!(planner.generator(1).unwrap().config().gpus_per_node, 4);
+        assert_eq!(planner.generator(2).unwrap().config().gpus_per_node, 2);
+
+        let (tp_size, template) = planner.get_pipeline_template(8).unwrap();
+        assert_eq!(tp_size, 1);
+        assert_eq!(template.stages.len(), 8);
+
+        // Nodes are not shared between pipelines
+        assert!(planner.get_pipeline_template(2).is_err());
+    }
+
+    #[test]
+    fn test_return_error_for_duplicate_tensor_parallel_degree() {
+        let profiles = vec![(2, layers(4, 1.0, 1.0)), (2, layers(4, 1.0, 1.0))];
+        assert!(TensorParallelPlanner::new(profiles, PlannerConfig::default()).is_err());
+        assert!(TensorParallelPlanner::new(vec![], PlannerConfig::default()).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use crate::config::DeviceClass;
+use crate::execution_result::LayerExecutionResult;
+use crate::PlannerError;
+use std::fmt;
+
+/// A problem in the input that would otherwise make planning panic or silently go wrong.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProfileError {
+    EmptyProfile,
+    // Layer indices must be 0, 1, ..., in order
+    DuplicateLayerIndex {
+        index: u32,
+    },
+    NonContiguousLayerIndex {
+        position: usize,
+        index: u32,
+    },
+    // Negative, NaN or infinite value of a layer
+    InvalidValue {
+        layer_index: u32,
+        field: &'static str,
+        value: f64,
+    },
+    InvalidDeviceClass {
+        name: String,
+        reason: String,
+    },
+    // No profiles to aggregate
+    NoProfiles,
+    // A profile file that cannot be read or parsed
+    UnreadableFile {
+        path: String,
+        reason: String,
+    },
+    InvalidMetadata {
+        field: &'static str,
+        reason: String,
+    },
+    // A trace that layer timings cannot be imported from
+    InvalidTrace {
+        reason: String,
+    },
+    // A profile taken for another model, parallelism or precision than planned for
+    MetadataMismatch {
+        field: &'static str,
+        expected: String,
+        found: String,
+    },
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileError::EmptyProfile => write!(f, "Profile has no layers"),
+            ProfileError::DuplicateLayerIndex { index } => {
+                write!(f, "Layer index {} appears more than once", index)
+            }
+            ProfileError::NonContiguousLayerIndex { position, index } => write!(
+                f,
+                "Layer at position {} has index {}; indices must be 0, 1, ... in order",
+                position, index
+            ),
+            ProfileError::InvalidValue {
+                layer_index,
+                field,
+                value,
+            } => write!(f, "Layer {} has invalid {}: {}", layer_index, field, value),
+            ProfileError::InvalidDeviceClass { name, reason } => {
+                write!(f, "Invalid device class {}: {}", name, reason)
+            }
+            ProfileError::NoProfiles => write!(f, "No profiles are given"),
+            ProfileError::UnreadableFile { path, reason } => {
+                write!(f, "Cannot read profile {}: {}", path, reason)
+            }
+            ProfileError::InvalidMetadata { field, reason } => {
+                write!(f, "Profile has invalid {}: {}", field, reason)
+            }
+            ProfileError::InvalidTrace { reason } => write!(f, "Invalid trace: {}", reason),
+            ProfileError::MetadataMismatch {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Profile has {} {}, but {} is planned for",
+                field, found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+fn check_value(
+    layer: &LayerExecutionResult,
+    field: &'static str,
+    value: f64,
+) -> Result<(), ProfileError> {
+    if value.is_finite() && value >= 0.0 {
+        Ok(())
+    } else {
+        Err(ProfileError::InvalidValue {
+            layer_index: layer.layer_index,
+            field,
+            value,
+        })
+    }
+}
+
+/// Checks that layers are indexed 0, 1, ... in order and that their latencies are valid.
+pub fn validate_profile(layers: &[LayerExecutionResult]) -> Result<(), ProfileError> {
+    if layers.is_empty() {
+        return Err(ProfileError::EmptyProfile);
+    }
+
+    for (position, layer) in layers.iter().enumerate() {
+        if layer.layer_index as usize != position {
+            if position > 0 && layer.layer_index == layers[position - 1].layer_index {
+                return Err(ProfileError::DuplicateLayerIndex {
+                    index: layer.layer_index,
+                });
+            }
+            return Err(ProfileError::NonContiguousLayerIndex {
+                position,
+                index: layer.layer_index,
+            });
+        }
+
+        check_value(layer, "forward", layer.forward)?;
+        check_value(layer, "backward", layer.backward)?;
+        check_value(layer, "recompute", layer.recompute)?;
+        check_value(layer, "forward_std", layer.forward_std)?;
+        check_value(layer, "backward_std", layer.backward_std)?;
+        for &sample in layer.samples.iter() {
+            check_value(layer, "samples", sample)?;
+        }
+    }
+    Ok(())
+}
+
+/// Checks that numbers of nodes to plan for are given and positive.
+pub fn validate_num_nodes(num_nodes: &[u32]) -> Result<(), PlannerError> {
+    if num_nodes.is_empty() {
+        return Err(PlannerError::invalid_config("No number of nodes is given"));
+    }
+    if num_nodes.contains(&0) {
+        return Err(PlannerError::invalid_config(
+            "Number of nodes must be positive",
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the speed and the profile of a device class.
+pub fn validate_device_class(class: &DeviceClass) -> Result<(), ProfileError> {
+    if !(class.speed.is_finite() && class.speed > 0.0) {
+        return Err(ProfileError::InvalidDeviceClass {
+            name: class.name.clone(),
+            reason: format!("speed must be positive, got {}", class.speed),
+        });
+    }
+    if let Some(profile) = &class.profile {
+        validate_profile(profile).map_err(|e| ProfileError::InvalidDeviceClass {
+            name: class.name.clone(),
+            reason: e.to_string(),
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layers(num_layers: u32) -> Vec<LayerExecutionResult> {
+        (0..num_layers)
+            .map(|i| LayerExecutionResult {
+                layer_index: i,
+                layer_name: format!("layer{}", i),
+                forward: 1.0,
+                backward: 1.0,
+                mem_required: 1,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_profile() {
+        assert_eq!(validate_profile(&layers(4)), Ok(()));
+        assert_eq!(validate_num_nodes(&[1, 2, 4]), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_layer_indices() {
+        assert_eq!(validate_profile(&[]), Err(ProfileError::EmptyProfile));
+
+        let mut profile = layers(4);
+        profile[2].layer_index = 1;
+        assert_eq!(
+            validate_profile(&profile),
+            Err(ProfileError::DuplicateLayerIndex { index: 1 })
+        );
+
+        let mut profile = layers(4);
+        profile[2].layer_index = 3;
+        assert_eq!(
+            validate_profile(&profile),
+            Err(ProfileError::NonContiguousLayerIndex {
+                position: 2,
+                index: 3
+            })
+        );
+
+        let mut profile = layers(4);
+        profile.swap(0, 1);
+        assert!(validate_profile(&profile).is_err());
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let mut profile = layers(4);
+        profile[1].forward = f64::NAN;
+        assert!(matches!(
+            validate_profile(&profile),
+            Err(ProfileError::InvalidValue {
+                layer_index: 1,
+                field: "forward",
+                ..
+            })
+        ));
+
+        let mut profile = layers(4);
+        profile[3].backward = -1.0;
+        assert_eq!(
+            validate_profile(&profile),
+            Err(ProfileError::InvalidValue {
+                layer_index: 3,
+                field: "backward",
+                value: -1.0
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_num_nodes() {
+        assert!(matches!(
+            validate_num_nodes(&[]),
+            Err(PlannerError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            validate_num_nodes(&[0, 1]),
+            Err(PlannerError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_device_class() {
+        assert!(validate_device_class(&DeviceClass::new("a100", 0.0)).is_err());
+        let mut class = DeviceClass::new("h100", 2.0);
+        assert_eq!(validate_device_class(&class), Ok(()));
+        class.profile = Some(vec![]);
+        assert!(validate_device_class(&class).is_err());
+    }
+}
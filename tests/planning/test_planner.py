@@ -1,11 +1,12 @@
 import itertools
+import json
 from pathlib import Path
 
 import pytest
 from cornstarch.pipeline_template import PipelineTemplate
 
 from oobleck.planning import planner
-from oobleck.planning.profiler import LayerExecutionResult
+from oobleck.planning.profiler import DeviceClass, LayerExecutionResult, ModelProfiler
 
 from ..conftest import (
     init_profile_data,
@@ -39,12 +40,84 @@ def profile_data(tmp_path: Path) -> list[LayerExecutionResult]:
 
 
 def test_error_for_too_large_num_nodes(profile_data: list[LayerExecutionResult]):
-    with pytest.raises(RuntimeError):
+    with pytest.raises(planner.InfeasibleNodeCountError) as e:
         planner.create_pipeline_templates(
             model_name=model_name,
             profile_data=profile_data,
             num_nodes=[len(modules) + 1],
         )
+    assert e.value.num_nodes == [len(modules) + 1]
+    assert isinstance(e.value, planner.PlannerError)
+
+
+def test_error_for_empty_num_nodes(profile_data: list[LayerExecutionResult]):
+    with pytest.raises(planner.InvalidConfigError):
+        planner.create_pipeline_templates(
+            model_name=model_name,
+            profile_data=profile_data,
+            num_nodes=[],
+        )
+
+    with pytest.raises(planner.InvalidConfigError) as e:
+        planner.create_heterogeneous_pipeline_template(
+            model_name=model_name,
+            profile_data=profile_data,
+            device_classes=[DeviceClass("a100"), DeviceClass("h100", speed=2.0)],
+            num_nodes=[0, 0],
+        )
+    assert e.value.reason == "At least one node is required"
+
+
+@pytest.mark.parametrize(
+    "num_microbatches, max_num_microbatches", [(0, None), (8, 4), (None, 8)]
+)
+def test_error_for_invalid_microbatch_range(
+    profile_data: list[LayerExecutionResult],
+    num_microbatches: int | None,
+    max_num_microbatches: int | None,
+):
+    with pytest.raises(planner.InvalidConfigError):
+        planner.create_pipeline_templates(
+            model_name=model_name,
+            profile_data=profile_data,
+            num_nodes=[1],
+            num_microbatches=num_microbatches,
+            max_num_microbatches=max_num_microbatches,
+        )
+
+
+@pytest.mark.parametrize("memory_headroom", [-0.1, 1.0, float("nan")])
+def test_error_for_invalid_memory_headroom(
+    profile_data: list[LayerExecutionResult], memory_headroom: float
+):
+    with pytest.raises(planner.InvalidConfigError):
+        planner.create_pipeline_templates(
+            model_name=model_name,
+            profile_data=profile_data,
+            num_nodes=[1],
+            device_memory=1 << 30,
+            memory_headroom=memory_headroom,
+        )
+
+    with pytest.raises(planner.InvalidConfigError):
+        planner.create_heterogeneous_pipeline_template(
+            model_name=model_name,
+            profile_data=profile_data,
+            device_classes=[DeviceClass("a100", memory=1 << 30)],
+            num_nodes=[1],
+            memory_headroom=memory_headroom,
+        )
+
+
+def test_error_for_nan_latency(profile_data: list[LayerExecutionResult]):
+    profile_data[0].forward = float("nan")
+    with pytest.raises(planner.InvalidProfileError) as e:
+        planner.create_pipeline_templates(
+            model_name=model_name,
+            profile_data=profile_data,
+            num_nodes=[1],
+        )
+    assert e.value.layer_index == 0
 
 
 def test_create_pipeline_templates(profile_data: list[LayerExecutionResult]):
@@ -62,3 +135,295 @@ def test_create_pipeline_templates(profile_data: list[LayerExecutionResult]):
         assert modules == list(
             itertools.chain.from_iterable(template.modules_per_stage)
         )
+
+
+def test_create_pipeline_templates_with_gpus_per_node(
+    profile_data: list[LayerExecutionResult],
+):
+    templates = planner.create_pipeline_templates(
+        model_name=model_name,
+        profile_data=profile_data,
+        num_nodes=[1, 2],
+        gpus_per_node=2,
+    )
+
+    assert list(templates.keys()) == [1, 2]
+    for num_nodes, template in templates.items():
+        assert template.num_stages == 2 * num_nodes
+        assert len(template.stages_per_node) == num_nodes
+
+
+def test_extend_pipeline_templates(profile_data: list[LayerExecutionResult]):
+    template_planner = planner.PipelineTemplatePlanner(model_name, profile_data)
+    templates = template_planner.create_pipeline_templates([1, 2])
+    assert [template.num_stages for template in templates.values()] == [1, 2]
+
+    extended = template_planner.create_pipeline_templates([2, 4])
+    assert [template.num_stages for template in extended.values()] == [2, 4]
+    assert extended[2].modules_per_stage == templates[2].modules_per_stage
+
+    expected = planner.create_pipeline_templates(
+        model_name=model_name, profile_data=profile_data, num_nodes=[4]
+    )
+    assert extended[4].modules_per_stage == expected[4].modules_per_stage
+
+    with pytest.raises(planner.InfeasibleNodeCountError):
+        template_planner.create_pipeline_templates([len(modules) + 1])
+
+
+def test_cancel_planning(profile_data: list[LayerExecutionResult]):
+    token = planner.CancellationToken()
+    token.cancel()
+    assert token.cancelled
+    with pytest.raises(planner.CancelledError) as e:
+        planner.create_pipeline_templates(
+            model_name=model_name,
+            profile_data=profile_data,
+            num_nodes=[1, 2],
+            cancellation_token=token,
+        )
+    assert isinstance(e.value, planner.PlannerError)
+
+    with pytest.raises(planner.CancelledError):
+        planner.create_pipeline_templates(
+            model_name=model_name,
+            profile_data=profile_data,
+            num_nodes=[1, 2],
+            timeout=0.0,
+        )
+
+    template_planner = planner.PipelineTemplatePlanner(model_name, profile_data)
+    template_planner.create_pipeline_templates([1, 2])
+    with pytest.raises(planner.CancelledError):
+        template_planner.create_pipeline_templates([4], cancellation_token=token)
+    templates = template_planner.create_pipeline_templates([2, 4], timeout=60.0)
+    assert [template.num_stages for template in templates.values()] == [2, 4]
+
+
+def test_create_pipeline_templates_from_profile(tmp_path: Path):
+    profile_dir_path = tmp_path / tag / "profile"
+    init_profile_data(
+        profile_dir=profile_dir_path,
+        tp_size=tp_size,
+        microbatch_size=microbatch_size,
+        precision=precision,
+    )
+    profile_path = ModelProfiler.get_profile_path(
+        profile_dir_path, tp_size, microbatch_size, precision
+    )
+
+    templates = planner.create_pipeline_templates_from_profile(
+        model_name=model_name,
+        profile_path=profile_path,
+        num_nodes=[1, 2],
+        tp_size=tp_size,
+        microbatch_size=microbatch_size,
+    )
+    assert [template.num_stages for template in templates.values()] == [1, 2]
+
+    with pytest.raises(planner.InvalidProfileError):
+        planner.create_pipeline_templates_from_profile(
+            model_name=model_name,
+            profile_path=profile_path,
+            num_nodes=[1],
+            tp_size=tp_size * 2,
+        )
+
+
+def test_create_microbatch_pipeline_templates(
+    profile_data: list[LayerExecutionResult],
+):
+    profiles = {}
+    for size in [1, 2, 3]:
+        profiles[size] = [
+            LayerExecutionResult(
+                layer_index=layer.layer_index,
+                layer_name=layer.layer_name,
+                forward=0.1 + layer.forward * size,
+                backward=0.1 + layer.backward * size,
+                mem_required=layer.mem_required * size,
+            )
+            for layer in profile_data
+        ]
+
+    templates = planner.create_microbatch_pipeline_templates(
+        model_name=model_name,
+        profile_data=profiles,
+        num_nodes=[1, 2],
+        global_batch_size=8,
+    )
+    assert list(templates.keys()) == [1, 2]
+    for num_nodes, (size, template) in templates.items():
+        # 3 does not divide the global batch
+        assert size in [1, 2]
+        assert template.num_stages == num_nodes
+
+    with pytest.raises(planner.InvalidConfigError):
+        planner.create_microbatch_pipeline_templates(
+            model_name=model_name,
+            profile_data=profiles,
+            num_nodes=[1],
+            global_batch_size=8,
+            num_microbatches=4,
+        )
+
+    with pytest.raises(planner.InvalidConfigError):
+        planner.create_microbatch_pipeline_templates(
+            model_name=model_name,
+            profile_data=profiles,
+            num_nodes=[1],
+            global_batch_size=8,
+            top_k=2,
+        )
+
+
+def test_save_profile(tmp_path: Path, profile_data: list[LayerExecutionResult]):
+    profile_path = tmp_path / "profile.json"
+    planner.save_profile(
+        profile_path, model_name, microbatch_size, tp_size, precision, profile_data
+    )
+
+    templates = planner.create_pipeline_templates_from_profile(
+        model_name=model_name,
+        profile_path=profile_path,
+        num_nodes=[1],
+        precision=precision,
+    )
+    assert len(templates) == 1
+
+    # Memory is modeled in the precision of the profile
+    templates = planner.create_pipeline_templates_from_profile(
+        model_name=model_name,
+        profile_path=profile_path,
+        num_nodes=[1],
+        optimizer="adam",
+    )
+    assert len(templates) == 1
+
+    with pytest.raises(planner.InvalidProfileError):
+        planner.create_pipeline_templates_from_profile(
+            model_name=model_name,
+            profile_path=profile_path,
+            num_nodes=[1],
+            precision="bf16",
+        )
+
+
+def test_import_chrome_trace(tmp_path: Path):
+    def event(cat: str, name: str, tid: int, ts: int, dur: int, **args) -> dict:
+        return dict(
+            ph="X", cat=cat, name=name, pid=1, tid=tid, ts=ts, dur=dur, args=args
+        )
+
+    events = []
+    for index, layer_name in enumerate(modules):
+        start = index * 100
+        events += [
+            event("user_annotation", layer_name, 1, start, 50),
+            event("cuda_runtime", "launch", 1, start + 10, 1, correlation=index),
+            event("kernel", "gemm", 7, start + 20, 2000, correlation=index),
+        ]
+    trace_path = tmp_path / "trace.json"
+    trace_path.write_text(json.dumps({"traceEvents": events}))
+
+    profile_data = planner.import_chrome_trace(trace_path, modules)
+    assert [layer.layer_name for layer in profile_data] == modules
+    assert all(layer.forward == 2.0 for layer in profile_data)
+
+    templates = planner.create_pipeline_templates(
+        model_name=model_name, profile_data=profile_data, num_nodes=[1]
+    )
+    assert len(templates) == 1
+
+
+def test_aggregate_profiles(tmp_path: Path, profile_data: list[LayerExecutionResult]):
+    profile_paths = []
+    for run, slowdown in enumerate([1.0, 1.0, 1.0, 2.0]):
+        for layer in profile_data:
+            layer.forward = slowdown
+        profile_paths.append(tmp_path / f"run{run}.json")
+        planner.save_profile(
+            profile_paths[-1],
+            model_name,
+            microbatch_size,
+            tp_size,
+            precision,
+            profile_data,
+        )
+
+    output_path = tmp_path / "profile.json"
+    report = planner.aggregate_profiles(profile_paths, output_path, estimator="median")
+    assert report["outlier_runs"] == [3]
+    assert [layer["layer_name"] for layer in report["layers"]] == modules
+
+    templates = planner.create_pipeline_templates_from_profile(
+        model_name=model_name, profile_path=output_path, num_nodes=[1]
+    )
+    assert len(templates) == 1
+
+
+def test_interpolate_profile(tmp_path: Path, profile_data: list[LayerExecutionResult]):
+    profile_paths = []
+    for size in [1, 4]:
+        for layer in profile_data:
+            layer.forward = float(size)
+            layer.mem_required = 10 * size
+        profile_paths.append(tmp_path / f"mb{size}.json")
+        planner.save_profile(
+            profile_paths[-1], model_name, size, tp_size, precision, profile_data
+        )
+
+    output_path = tmp_path / "mb2.json"
+    report = planner.interpolate_profile(profile_paths, 2, output_path)
+    assert report["latency_error"] == pytest.approx(0.0)
+
+    templates = planner.create_pipeline_templates_from_profile(
+        model_name=model_name,
+        profile_path=output_path,
+        num_nodes=[1],
+        microbatch_size=2,
+    )
+    assert len(templates) == 1
+
+
+def test_synthesize_profile_from_config(tmp_path: Path):
+    config_path = tmp_path / "config.json"
+    config_path.write_text(
+        json.dumps(
+            {
+                "architectures": ["GPT2LMHeadModel"],
+                "model_type": "gpt2",
+                "n_embd": 768,
+                "n_head": 12,
+                "n_layer": 12,
+                "n_positions": 1024,
+                "vocab_size": 50257,
+            }
+        )
+    )
+
+    profile_path = tmp_path / "profile.json"
+    synthesized_model_name = planner.synthesize_profile_from_config(
+        tmp_path, profile_path, peak_flops=312e12, memory_bandwidth=2e12
+    )
+    assert synthesized_model_name == "transformers.GPT2LMHeadModel"
+
+    templates = planner.create_pipeline_templates_from_profile(
+        model_name=synthesized_model_name,
+        profile_path=profile_path,
+        num_nodes=[2, 4],
+        precision="bf16",
+        optimizer="adamw",
+    )
+    assert [template.num_stages for template in templates.values()] == [2, 4]
+
+    layer_names = (
+        ["transformer.wte"]
+        + [f"transformer.h.{i}" for i in range(12)]
+        + ["transformer.ln_f", "lm_head"]
+    )
+    for num_stages, template in templates.items():
+        assert num_stages == template.num_stages
+        assert layer_names == list(
+            itertools.chain.from_iterable(template.modules_per_stage)
+        )